@@ -2,7 +2,7 @@
 #![allow(unused_variables)]
 use halo2_base::AssignedValue;
 use halo2_base::QuantumCell::{Constant, self};
-use halo2_base::gates::builder::{GateCircuitBuilder, GateThreadBuilder, CircuitBuilderStage, MultiPhaseThreadBreakPoints, RangeCircuitBuilder};
+use halo2_base::gates::builder::{GateCircuitBuilder, GateThreadBuilder, CircuitBuilderStage, MultiPhaseThreadBreakPoints, RangeCircuitBuilder, RangeWithInstanceCircuitBuilder};
 use halo2_base::gates::flex_gate::{FlexGateConfig, GateChip, GateInstructions, GateStrategy};
 use halo2_base::halo2_proofs::poly::kzg::multiopen::VerifierSHPLONK;
 use halo2_base::halo2_proofs::poly::kzg::strategy::SingleStrategy;
@@ -27,7 +27,11 @@ use halo2_base::{
 use itertools::Itertools;
 use rand::rngs::OsRng;
 use rand::Rng;
-use zkimg::util::{generate_image, crop_image};
+use halo2_base::gates::RangeChip;
+use zkimg::circuit::{build_circuit, crop_and_commit};
+use zkimg::config::{CircuitConfig, CircuitParams, ConfiguredCircuit};
+use zkimg::srs::SrsManager;
+use zkimg::util::{generate_image, crop_image, commit_image};
 use std::marker::PhantomData;
 use std::mem::size_of;
 
@@ -40,113 +44,87 @@ use get_size::GetSize;
 
 const WIDTH: usize = 1280;
 const HEIGHT: usize = 720;
+const CHANNELS: usize = 3;
+const BIT_DEPTH: usize = 8;
 const CROPWIDTH: usize = 720;
 const CROPHEIGHT: usize = 480;
 const STARTX: usize = 0;
 const STARTY: usize = 0;
+const LOOKUP_BITS: usize = 19;
 
 
 fn crop<F: ScalarField>(
-    ctx: &mut Context<F>, 
-    original_image: Vec<F>, 
-    cropped_image: Vec<F>,
-    crop_startx: usize,
-    crop_starty: usize,
-    crop_width: usize,
-    crop_height: usize,
+    builder: &mut GateThreadBuilder<F>,
+    img: &Vec<u64>,
+    params: &CircuitParams,
+    make_public: &mut Vec<AssignedValue<F>>,
 ) {
-    let original_witness = ctx.assign_witnesses(original_image);
-    // let cropped_witness = ctx.assign_witnesses(cropped_image);
-
-    let chip = GateChip::default();
-    
-    // let mut constraints = vec![];
-    // let mut is_eq = ctx.load(F::one());
-    for new_y in 0..crop_height {
-        for new_x in 0..crop_width {
-            for rgb in 0..3 {
-                let old_x = crop_startx + new_x;
-                let old_y = crop_starty + new_y;
-    
-                let old_index = (old_y * WIDTH + old_x) * 3;
-                let new_index = (new_y * crop_width + new_x) * 3;
-
-                // let c = chip.is_equal(
-                //     ctx,
-                //     QuantumCell::Existing(original_witness[old_index+rgb]),
-                //     QuantumCell::Constant(cropped_image[new_index+rgb]),
-                // );
-                
-                chip.assert_is_const(
-                    ctx,
-                    &original_witness[old_index+rgb],
-                    &cropped_image[new_index+rgb],
-                );
-            }
-        }
-    }
+    let range = RangeChip::<F>::default(params.lookup_bits);
+    crop_and_commit(
+        builder,
+        &range,
+        img,
+        WIDTH,
+        HEIGHT,
+        CHANNELS,
+        BIT_DEPTH,
+        STARTX,
+        STARTY,
+        CROPWIDTH,
+        CROPHEIGHT,
+        make_public,
+    )
+    .expect("image should fit the crop circuit");
 }
 
 fn crop_circuit(
-    k: usize,
     img: &Vec<u64>,
-    cropped: &Vec<u64>,
     stage: CircuitBuilderStage,
-    break_points: Option<MultiPhaseThreadBreakPoints>,
-) -> RangeCircuitBuilder<Fr> {
+    config: &mut CircuitConfig,
+) -> ConfiguredCircuit<Fr> {
     let mut builder = match stage {
         CircuitBuilderStage::Mock => GateThreadBuilder::mock(),
         CircuitBuilderStage::Prover => GateThreadBuilder::prover(),
         CircuitBuilderStage::Keygen => GateThreadBuilder::keygen(),
     };
 
-    crop(
-        builder.main(0), 
-        img.iter().map(|&x| Fr::from(x)).collect(),
-        cropped.iter().map(|&x| Fr::from(x)).collect(),
-        STARTX, 
-        STARTY,
-        CROPWIDTH,
-        CROPHEIGHT
-    );
+    let mut assigned_instances = vec![];
+    crop(&mut builder, &img, &config.params, &mut assigned_instances);
 
-    let circuit = match stage {
-        CircuitBuilderStage::Mock => {
-            builder.config(k as usize,None);
-            RangeCircuitBuilder::mock(builder)
-        }
-        CircuitBuilderStage::Keygen => {
-            builder.config(k as usize,None);
-            RangeCircuitBuilder::keygen(builder)
-        }
-        CircuitBuilderStage::Prover => RangeCircuitBuilder::prover(builder, break_points.unwrap()),
-    };
-    circuit
+    build_circuit(stage, builder, assigned_instances, config).expect("circuit should match keygen config")
+}
+
+/// The checked-in SRS when there is one for `k`, otherwise an insecure local
+/// setup, which only matters for timing.
+fn load_params(k: u32) -> ParamsKZG<Bn256> {
+    let srs = SrsManager::new("params");
+    srs.load(k).unwrap_or_else(|_| {
+        eprintln!("no SRS for k = {k} in {:?}, using an insecure local setup", srs.dir());
+        srs.dev_mode(true).load(k).unwrap()
+    })
 }
 
 fn bench(c: &mut Criterion) {
     let k:u32 = 20;
-    let img = generate_image(WIDTH, HEIGHT);
-    let cropped = crop_image(&img, WIDTH, STARTX, STARTY, CROPWIDTH, CROPHEIGHT);
-    let circuit = crop_circuit(k as usize, &img, &cropped, CircuitBuilderStage::Keygen, None);
-    let params = ParamsKZG::<Bn256>::setup(k, OsRng);
+    let img = generate_image(WIDTH, HEIGHT, CHANNELS, BIT_DEPTH).unwrap();
+    // the keys only depend on the image dimensions, so keygen runs on a blank image
+    let blank = vec![0; WIDTH * HEIGHT * CHANNELS];
+    let mut config = CircuitConfig::new(CircuitParams::new(k as usize, LOOKUP_BITS, None));
+    let circuit = crop_circuit(&blank, CircuitBuilderStage::Keygen, &mut config);
+    let params = load_params(k);
     let vk: VerifyingKey<G1Affine> = keygen_vk(&params, &circuit).expect("vk should not fail");
     let pk = keygen_pk(&params, vk, &circuit).expect("pk should not fail");
-    let break_points = circuit.0.break_points.take();
-    println!("{:?}",break_points);
+    config.break_points = circuit.break_points();
     drop(circuit);
 
-    let mut builder = GateThreadBuilder::new(true);
-    crop(
-        builder.main(0), 
-        img.iter().map(|&x| Fr::from(x)).collect(),
-        cropped.iter().map(|&x| Fr::from(x)).collect(),
-        STARTX, 
-        STARTY,
-        CROPWIDTH,
-        CROPHEIGHT
-    );
-    let circuit = RangeCircuitBuilder::prover(builder, break_points.clone());
+    // what the verifier knows: commitments to the signed original and the published crop
+    let cropped = crop_image(&img, WIDTH, CHANNELS, STARTX, STARTY, CROPWIDTH, CROPHEIGHT).unwrap();
+    let instances = vec![
+        commit_image::<Fr>(&img, WIDTH, HEIGHT, CHANNELS, BIT_DEPTH).unwrap(),
+        commit_image::<Fr>(&cropped, CROPWIDTH, CROPHEIGHT, CHANNELS, BIT_DEPTH).unwrap(),
+    ];
+
+    let circuit = crop_circuit(&img, CircuitBuilderStage::Prover, &mut config.clone());
 
     let mut transcript: Blake2bWrite<Vec<u8>, G1Affine, Challenge255<G1Affine>> = Blake2bWrite::<_, _, Challenge255<_>>::init(vec![]);
     create_proof::<
@@ -156,7 +134,7 @@ fn bench(c: &mut Criterion) {
         _,
         Blake2bWrite<Vec<u8>, G1Affine, Challenge255<_>>,
         _,
-    >(&params, &pk, &[circuit], &[&[]], OsRng, &mut transcript)
+    >(&params, &pk, &[circuit], &[&[&instances[..]]], OsRng, &mut transcript)
     .expect("prover should not fail");
 
     let proof = transcript.finalize();
@@ -167,13 +145,12 @@ fn bench(c: &mut Criterion) {
     keygen_group.sample_size(10);
     keygen_group.bench_with_input(
         BenchmarkId::new("crop-keygen", k),
-        &(&k, &img, &cropped),
-        |bencher, &(k, img, cropped)| {
+        &(&k, &params, &blank),
+        |bencher, &(k, params, blank)| {
             bencher.iter(|| {
-                let circuit = crop_circuit(*k as usize, &img, &cropped, CircuitBuilderStage::Keygen, None);
-                let params = ParamsKZG::<Bn256>::setup(*k, OsRng);
-                let vk: VerifyingKey<G1Affine> = keygen_vk(&params, &circuit).expect("vk should not fail");
-                let pk = keygen_pk(&params, vk, &circuit).expect("pk should not fail");
+                let circuit = crop_circuit(&blank, CircuitBuilderStage::Keygen, &mut CircuitConfig::new(CircuitParams::new(*k as usize, LOOKUP_BITS, None)));
+                let vk: VerifyingKey<G1Affine> = keygen_vk(params, &circuit).expect("vk should not fail");
+                let pk = keygen_pk(params, vk, &circuit).expect("pk should not fail");
             })
         },
     );
@@ -183,20 +160,10 @@ fn bench(c: &mut Criterion) {
     group.sample_size(10);
     group.bench_with_input(
         BenchmarkId::new("crop-proof", k),
-        &(&params, &pk, &img, &cropped),
-        |bencher, &(params, pk, img, cropped)| {
+        &(&params, &pk, &img, &instances),
+        |bencher, &(params, pk, img, instances)| {
             bencher.iter(|| {
-                let mut builder = GateThreadBuilder::new(true);
-                crop(
-                    builder.main(0), 
-                    img.iter().map(|&x| Fr::from(x)).collect(),
-                    cropped.iter().map(|&x| Fr::from(x)).collect(),
-                    STARTX, 
-                    STARTY,
-                    CROPWIDTH,
-                    CROPHEIGHT
-                );
-                let circuit = RangeCircuitBuilder::prover(builder, break_points.clone());
+                let circuit = crop_circuit(&img, CircuitBuilderStage::Prover, &mut config.clone());
 
                 let mut transcript: Blake2bWrite<Vec<u8>, G1Affine, Challenge255<G1Affine>> = Blake2bWrite::<_, _, Challenge255<_>>::init(vec![]);
                 create_proof::<
@@ -206,7 +173,7 @@ fn bench(c: &mut Criterion) {
                     _,
                     Blake2bWrite<Vec<u8>, G1Affine, Challenge255<_>>,
                     _,
-                >(params, pk, &[circuit], &[&[]], OsRng, &mut transcript)
+                >(params, pk, &[circuit], &[&[&instances[..]]], OsRng, &mut transcript)
                 .expect("prover should not fail");
                 let proof = transcript.finalize();
             })
@@ -214,8 +181,8 @@ fn bench(c: &mut Criterion) {
     );
     group.bench_with_input(
         BenchmarkId::new("crop-verify", k),
-        &(&params, &pk, &proof),
-        |bencher, &(params, pk, proof)| {
+        &(&params, &pk, &proof, &instances),
+        |bencher, &(params, pk, proof, instances)| {
             bencher.iter(|| {
                 let mut transcripts: Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>> = Blake2bRead::<_, _, Challenge255<_>>::init(&proof[..]);
                 let strategy = SingleStrategy::new(params);
@@ -225,7 +192,7 @@ fn bench(c: &mut Criterion) {
                     Challenge255<G1Affine>,
                     Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
                     SingleStrategy<'_, Bn256>,
-                >(params, &pk.get_vk(), strategy, &[&[]], &mut transcripts);
+                >(params, &pk.get_vk(), strategy, &[&[&instances[..]]], &mut transcripts);
                 if res.is_err() {
                     println!("{:?}",res);
                 }
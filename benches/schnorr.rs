@@ -1,8 +1,7 @@
 use halo2_base::{
     gates::{
         builder::{
-            CircuitBuilderStage, GateThreadBuilder, MultiPhaseThreadBreakPoints,
-            RangeCircuitBuilder,
+            CircuitBuilderStage, GateThreadBuilder,
         },
         GateInstructions,
         RangeChip,
@@ -27,6 +26,9 @@ use rand::rngs::OsRng;
 
 use criterion::{criterion_group, criterion_main};
 use criterion::{BenchmarkId, Criterion};
+use zkimg::circuit::{build_circuit, parallelize};
+use zkimg::config::{CircuitConfig, CircuitParams, ConfiguredCircuit};
+use zkimg::srs::SrsManager;
 use zkimg::util::{random_schnorr_signature_input, SchnorrInput};
 
 
@@ -34,7 +36,7 @@ const K: usize = 13;
 
 pub fn schnorr_verify_no_pubkey_check<F: PrimeField, CF: PrimeField, SF: PrimeField, GA>(
     chip: &EccChip<F, FpChipField<F, CF>>,
-    ctx: &mut Context<F>,
+    builder: &mut GateThreadBuilder<F>,
     pubkey: EcPoint<F, <FpChipField<F, CF> as FieldChip<F>>::FieldPoint>,
     r: ProperCrtUint<F>,       // int(sig[0:32]); fail if r ≥ p.
     s: ProperCrtUint<F>,       // int(sig[32:64]); fail if s ≥ n
@@ -49,6 +51,7 @@ where
     let scalar_chip =
     FpChipField::<F, SF>::new(base_chip.range, base_chip.limb_bits, base_chip.num_limbs);
 
+    let ctx = builder.main(0);
     // check r < p
     let r_valid = base_chip.is_less_than_p(ctx, &r);
     // check 0 < s < n
@@ -56,23 +59,31 @@ where
     // check 0 < e < n
     let e_valid = scalar_chip.is_soft_nonzero(ctx, &msg_hash);
 
-    // compute s * G and msgHash * pubkey
-    let s_g = fixed_base::scalar_multiply(
-        base_chip,
-        ctx,
-        &GA::generator(),
-        s.limbs().to_vec(),
-        base_chip.limb_bits,
-        fixed_window_bits,
-    );
-    let e_p = scalar_multiply::<_, _, GA>(
-        base_chip,
-        ctx,
-        pubkey,
-        msg_hash.limbs().to_vec(),
-        base_chip.limb_bits,
-        var_window_bits,
-    );
+    // compute s * G and msgHash * pubkey, each in its own thread
+    let mut products = parallelize(builder, vec![true, false], |ctx, fixed| {
+        if fixed {
+            fixed_base::scalar_multiply(
+                base_chip,
+                ctx,
+                &GA::generator(),
+                s.limbs().to_vec(),
+                base_chip.limb_bits,
+                fixed_window_bits,
+            )
+        } else {
+            scalar_multiply::<_, _, GA>(
+                base_chip,
+                ctx,
+                pubkey.clone(),
+                msg_hash.limbs().to_vec(),
+                base_chip.limb_bits,
+                var_window_bits,
+            )
+        }
+    });
+    let e_p = products.pop().unwrap();
+    let s_g = products.pop().unwrap();
+    let ctx = builder.main(0);
 
     // check s_G.x != e_P.x, which is a requirement for sub_unequal
     let x_eq = base_chip.is_equal(ctx, &s_g.x, &e_p.x);
@@ -106,16 +117,14 @@ where
 
 
 fn schnorr<F: PrimeField>(
-    ctx: &mut Context<F>,
-    lookup_bits: usize,
-    limb_bits: usize,
-    num_limbs: usize,
+    builder: &mut GateThreadBuilder<F>,
+    params: &CircuitParams,
     input: SchnorrInput
 ){
-    std::env::set_var("LOOKUP_BITS", lookup_bits.to_string());
-    let range = RangeChip::<F>::default(lookup_bits);
-    let fp_chip = FpChip::<F>::new(&range, limb_bits, num_limbs);
-    let fq_chip = FqChip::<F>::new(&range, limb_bits, num_limbs);
+    let ctx = builder.main(0);
+    let range = RangeChip::<F>::default(params.lookup_bits);
+    let fp_chip = FpChip::<F>::new(&range, params.limb_bits, params.num_limbs);
+    let fq_chip = FqChip::<F>::new(&range, params.limb_bits, params.num_limbs);
 
     let [m, s] = [input.msg_hash, input.s].map(|x| fq_chip.load_private(ctx, x));
     let r = fp_chip.load_private(ctx, input.r);
@@ -124,16 +133,15 @@ fn schnorr<F: PrimeField>(
     let pk = ecc_chip.assign_point(ctx, input.pk);
 
     let res = schnorr_verify_no_pubkey_check::<F, Fp, Fq, Secp256k1Affine>(
-        &ecc_chip, ctx, pk, r, s, m, 4, 4,
+        &ecc_chip, builder, pk, r, s, m, params.var_window_bits, params.fixed_window_bits,
     );
     assert_eq!(res.value(), &F::one());
 }
 
 fn schnorr_circuit(
     stage: CircuitBuilderStage,
-    break_points: Option<MultiPhaseThreadBreakPoints>,
-) -> RangeCircuitBuilder<Fr> {
-    let k = K;
+    config: &mut CircuitConfig,
+) -> ConfiguredCircuit<Fr> {
     let mut builder = match stage {
         CircuitBuilderStage::Mock => GateThreadBuilder::mock(),
         CircuitBuilderStage::Prover => GateThreadBuilder::prover(),
@@ -141,37 +149,25 @@ fn schnorr_circuit(
     };
 
     schnorr(
-        builder.main(0),
-        k - 1, 
-        88, 
-        3, 
+        &mut builder,
+        &config.params,
         random_schnorr_signature_input()
     );
 
-    let circuit = match stage {
-        CircuitBuilderStage::Mock => {
-            builder.config(k, Some(20));
-            RangeCircuitBuilder::mock(builder)
-        }
-        CircuitBuilderStage::Keygen => {
-            builder.config(k, Some(20));
-            RangeCircuitBuilder::keygen(builder)
-        }
-        CircuitBuilderStage::Prover => RangeCircuitBuilder::prover(builder, break_points.unwrap()),
-    };
-    circuit
+    build_circuit(stage, builder, vec![], config).expect("circuit should match keygen config")
 }
 
 fn bench(c: &mut Criterion) {
     let k:u32 = K as u32;
-    let circuit = schnorr_circuit(CircuitBuilderStage::Keygen, None);
-    let params = ParamsKZG::<Bn256>::setup(K as u32, OsRng);
+    let mut config = CircuitConfig::new(CircuitParams::new(K, K - 1, Some(20)));
+    let circuit = schnorr_circuit(CircuitBuilderStage::Keygen, &mut config);
+    let params = SrsManager::new("params").dev_mode(true).load(K as u32).unwrap();
     let vk = keygen_vk(&params, &circuit).expect("vk should not fail");
     let pk = keygen_pk(&params, vk, &circuit).expect("pk should not fail");
-    let break_points = circuit.0.break_points.take();
+    config.break_points = circuit.break_points();
     drop(circuit);
 
-    let circuit = schnorr_circuit(CircuitBuilderStage::Prover, Some(break_points.clone()));
+    let circuit = schnorr_circuit(CircuitBuilderStage::Prover, &mut config.clone());
 
     let mut transcript = Blake2bWrite::<_, _, Challenge255<_>>::init(vec![]);
     create_proof::<
@@ -181,7 +177,7 @@ fn bench(c: &mut Criterion) {
         _,
         Blake2bWrite<Vec<u8>, G1Affine, Challenge255<_>>,
         _,
-    >(&params, &pk, &[circuit], &[&[]], OsRng, &mut transcript)
+    >(&params, &pk, &[circuit], &[&[&[]]], OsRng, &mut transcript)
     .expect("prover should not fail");
     let proof = transcript.finalize();    
     println!("{:?}",proof.len());
@@ -193,8 +189,8 @@ fn bench(c: &mut Criterion) {
         &(&k),
         |bencher, &(k)| {
             bencher.iter(|| {
-                let circuit = schnorr_circuit(CircuitBuilderStage::Keygen, None);
-                let params = ParamsKZG::<Bn256>::setup(*k, OsRng);
+                let circuit = schnorr_circuit(CircuitBuilderStage::Keygen, &mut CircuitConfig::new(CircuitParams::new(K, K - 1, Some(20))));
+                let params = SrsManager::new("params").dev_mode(true).load(*k).unwrap();
                 let vk: VerifyingKey<G1Affine> = keygen_vk(&params, &circuit).expect("vk should not fail");
                 let pk = keygen_pk(&params, vk, &circuit).expect("pk should not fail");
             })
@@ -206,8 +202,7 @@ fn bench(c: &mut Criterion) {
         &(&params, &pk),
         |bencher, &(params, pk)| {
             bencher.iter(|| {
-                let circuit =
-                    schnorr_circuit(CircuitBuilderStage::Prover, Some(break_points.clone()));
+                let circuit = schnorr_circuit(CircuitBuilderStage::Prover, &mut config.clone());
 
                 let mut transcript = Blake2bWrite::<_, _, Challenge255<_>>::init(vec![]);
                 create_proof::<
@@ -217,7 +212,7 @@ fn bench(c: &mut Criterion) {
                     _,
                     Blake2bWrite<Vec<u8>, G1Affine, Challenge255<_>>,
                     _,
-                >(params, pk, &[circuit], &[&[]], OsRng, &mut transcript)
+                >(params, pk, &[circuit], &[&[&[]]], OsRng, &mut transcript)
                 .expect("prover should not fail");
                 transcript.finalize();
             })
@@ -237,7 +232,7 @@ fn bench(c: &mut Criterion) {
                     Challenge255<G1Affine>,
                     Blake2bRead<&[u8], G1Affine, Challenge255<G1Affine>>,
                     SingleStrategy<'_, Bn256>,
-                >(params, &pk.get_vk(), strategy, &[&[]], &mut transcripts);
+                >(params, &pk.get_vk(), strategy, &[&[&[]]], &mut transcripts);
                 if res.is_err() {
                     println!("{:?}",res);
                 }
@@ -0,0 +1,388 @@
+//! Aggregation of many proofs into one, in snark-verifier's accumulator
+//! style. The aggregation circuit runs the succinct part of the PLONK verifier
+//! on each proof and folds the resulting KZG accumulators into one, which it
+//! exposes as public instances next to every proof's own instances. The final
+//! pairing check on that accumulator is left to
+//! [`crate::verify::verify_aggregate`].
+//!
+//! The circuit replays the transcripts of the aggregated proofs, so they must
+//! be made with [`Transcript::Poseidon`]. The aggregated proofs' verifying key
+//! becomes part of the aggregation circuit, so aggregation keys are tied to
+//! one inner key directory and a fixed number of proofs.
+
+use std::{fs, path::Path, rc::Rc};
+
+use halo2_base::{
+    gates::{
+        builder::{CircuitBuilderStage, GateThreadBuilder},
+        RangeChip,
+    },
+    halo2_proofs::{
+        arithmetic::Field,
+        halo2curves::bn256::{Bn256, Fr, G1Affine},
+        plonk::{Circuit, ProvingKey, VerifyingKey},
+        poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    },
+    AssignedValue,
+};
+use halo2_ecc::{bn254::FpChip, ecc::BaseFieldEccChip};
+use itertools::Itertools;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use snark_verifier::{
+    loader::{halo2, native::NativeLoader},
+    pcs::{
+        kzg::{Bdfg21, KzgAccumulator, KzgAs, KzgSuccinctVerifyingKey, LimbsEncoding},
+        AccumulationScheme, AccumulationSchemeProver,
+    },
+    system::halo2::{compile, Config},
+    verifier::{
+        plonk::{PlonkProtocol, PlonkSuccinctVerifier},
+        SnarkVerifier,
+    },
+};
+
+use crate::{
+    bundle::{AggregateBundle, CircuitId, ProofBundle, Statement, Transcript},
+    circuit::build_circuit,
+    config::{CircuitConfig, ConfiguredCircuit},
+    error::{Error, Result},
+    keys::CircuitSpec,
+    prover::gen_proof_with,
+    verify::{verify, PoseidonTranscript, ACCUMULATOR_INSTANCES, ACCUMULATOR_LIMBS, ACCUMULATOR_LIMB_BITS},
+};
+
+const AGGREGATE_SPEC_FILE: &str = "aggregate.json";
+/// Circuit id of [`AggregateSpec`].
+pub const AGGREGATE_CIRCUIT: &str = "aggregate";
+
+pub(crate) type As = KzgAs<Bn256, Bdfg21>;
+type SuccinctVerifier = PlonkSuccinctVerifier<As, LimbsEncoding<ACCUMULATOR_LIMBS, ACCUMULATOR_LIMB_BITS>>;
+pub(crate) type Svk = KzgSuccinctVerifyingKey<G1Affine>;
+pub(crate) type Halo2Loader<'chip> = halo2::Halo2Loader<G1Affine, BaseFieldEccChip<'chip, G1Affine>>;
+type NativeAccumulator = KzgAccumulator<G1Affine, NativeLoader>;
+pub(crate) type LoadedAccumulator<'chip> = KzgAccumulator<G1Affine, Rc<Halo2Loader<'chip>>>;
+
+pub(crate) fn snark_error(e: snark_verifier::Error) -> Error {
+    Error::Aggregation(format!("{e:?}"))
+}
+
+/// A proof to aggregate, with the protocol snark-verifier compiles from its
+/// verifying key.
+#[derive(Clone, Debug)]
+pub struct Snark {
+    pub(crate) protocol: PlonkProtocol<G1Affine>,
+    statement: Statement,
+    pub(crate) instances: Vec<Fr>,
+    pub(crate) proof: Vec<u8>,
+}
+
+impl Snark {
+    /// Verifies `bundle` against `vk` and its `spec` first, so a bad proof is
+    /// reported here rather than as an unsatisfied aggregation circuit.
+    /// `params` are the ones of the inner keys.
+    pub fn new(
+        params: &ParamsKZG<Bn256>,
+        vk: &VerifyingKey<G1Affine>,
+        spec: &CircuitSpec,
+        bundle: &ProofBundle,
+    ) -> Result<Self> {
+        if bundle.transcript != Transcript::Poseidon {
+            return Err(Error::UnsupportedTranscript(bundle.transcript));
+        }
+        let statement = verify(params, vk, spec, bundle)?;
+        let protocol = compile(params, vk, Config::kzg().with_num_instance(vec![bundle.instances.len()]));
+        Ok(Self { protocol, statement, instances: bundle.instances.clone(), proof: bundle.proof.clone() })
+    }
+
+    /// A proof of the blank image made with the keys of `spec`, for keygen,
+    /// which still has to verify proofs natively.
+    pub fn blank(
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        spec: &CircuitSpec,
+    ) -> Result<Self> {
+        let (bundle, _) = spec.prove::<ConfiguredCircuit<Fr>>(
+            params,
+            pk,
+            config,
+            &spec.blank(),
+            (0, 0),
+            // any salt fits hiding inner keys
+            Some(Fr::zero()),
+            Transcript::Poseidon,
+        )?;
+        Self::new(params, pk.get_vk(), spec, &bundle)
+    }
+
+    pub fn statement(&self) -> &Statement {
+        &self.statement
+    }
+}
+
+/// What an aggregation key directory proves: `count` proofs made with the
+/// keys of `inner`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateSpec {
+    pub circuit: CircuitId,
+    pub inner: CircuitSpec,
+    pub count: usize,
+}
+
+impl AggregateSpec {
+    pub fn new(inner: CircuitSpec, count: usize) -> Self {
+        Self { circuit: CircuitId::new(AGGREGATE_CIRCUIT), inner, count }
+    }
+
+    /// Reads the spec written next to the aggregation keys.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(dir.as_ref().join(AGGREGATE_SPEC_FILE))?)?)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(dir.as_ref().join(AGGREGATE_SPEC_FILE), serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// The accumulator limbs followed by the instances of each proof.
+    pub fn num_instance(&self) -> usize {
+        ACCUMULATOR_INSTANCES + self.count * self.inner.num_instance()
+    }
+
+    /// Keygen runs on `count` copies of [`Snark::blank`] for the inner keys.
+    pub fn blank_snarks(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+    ) -> Result<Vec<Snark>> {
+        Ok(vec![Snark::blank(params, pk, config, &self.inner)?; self.count])
+    }
+
+    pub fn circuit(
+        &self,
+        stage: CircuitBuilderStage,
+        params: &ParamsKZG<Bn256>,
+        snarks: &[Snark],
+        config: &mut CircuitConfig,
+    ) -> Result<ConfiguredCircuit<Fr>> {
+        if snarks.len() != self.count {
+            return Err(Error::Aggregation(format!(
+                "keys aggregate {} proofs, got {}",
+                self.count,
+                snarks.len()
+            )));
+        }
+        let svk: Svk = params.get_g()[0].into();
+        let mut builder = GateThreadBuilder::from_stage(stage);
+        let assigned_instances = aggregate(&mut builder, &svk, snarks, config)?;
+        build_circuit(stage, builder, assigned_instances, config)
+    }
+
+    /// Proves the aggregation of `snarks` and returns its bundle. `C` is the
+    /// circuit type `pk` was read with.
+    pub fn prove<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        snarks: &[Snark],
+        transcript: Transcript,
+    ) -> Result<AggregateBundle> {
+        let circuit = self.circuit(CircuitBuilderStage::Prover, params, snarks, config)?;
+        let instances = circuit.instance();
+        let proof = gen_proof_with(params, pk, C::from(circuit), &instances, transcript)?;
+        let statements = snarks.iter().map(|snark| snark.statement.clone()).collect();
+        Ok(AggregateBundle::new(self.circuit.clone(), pk.get_vk(), transcript, statements, instances, proof))
+    }
+}
+
+/// Assigns the aggregation of `snarks` into `builder` and returns its public
+/// instances: the limbs of the folded accumulator, then each proof's
+/// instances.
+fn aggregate(
+    builder: &mut GateThreadBuilder<Fr>,
+    svk: &Svk,
+    snarks: &[Snark],
+    config: &CircuitConfig,
+) -> Result<Vec<AssignedValue<Fr>>> {
+    let mut accumulators = vec![];
+    for snark in snarks {
+        accumulators.extend(succinct_verify_native(svk, &snark.protocol, &snark.instances, &snark.proof)?);
+    }
+    let as_proof = fold_native(&accumulators)?;
+
+    let range = RangeChip::<Fr>::default(config.params.lookup_bits);
+    let fp_chip = FpChip::<Fr>::new(&range, ACCUMULATOR_LIMB_BITS, ACCUMULATOR_LIMBS);
+    let loader = Halo2Loader::new(BaseFieldEccChip::new(&fp_chip), std::mem::take(builder));
+
+    let mut accumulators = vec![];
+    let mut statement_instances = vec![];
+    for snark in snarks {
+        let protocol = snark.protocol.loaded(&loader);
+        let (instances, accumulator) = succinct_verify(svk, &loader, &protocol, &snark.instances, &snark.proof)?;
+        accumulators.extend(accumulator);
+        statement_instances.extend(instances);
+    }
+    let accumulator = fold(&loader, &accumulators, &as_proof)?;
+
+    let mut assigned_instances = accumulator_limbs(accumulator);
+    assigned_instances.extend(statement_instances);
+    *builder = loader.take_ctx();
+    Ok(assigned_instances)
+}
+
+/// The accumulators of one proof, computed outside the circuit.
+pub(crate) fn succinct_verify_native(
+    svk: &Svk,
+    protocol: &PlonkProtocol<G1Affine>,
+    instances: &[Fr],
+    proof: &[u8],
+) -> Result<Vec<NativeAccumulator>> {
+    let instances = [instances.to_vec()];
+    let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(proof);
+    let proof = SuccinctVerifier::read_proof(svk, protocol, &instances, &mut transcript).map_err(snark_error)?;
+    SuccinctVerifier::verify(svk, protocol, &instances, &proof).map_err(snark_error)
+}
+
+/// The accumulation proof folding `accumulators`, which the circuit reads as a
+/// witness.
+pub(crate) fn fold_native(accumulators: &[NativeAccumulator]) -> Result<Vec<u8>> {
+    let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(vec![]);
+    As::create_proof(&Default::default(), accumulators, &mut transcript, OsRng).map_err(snark_error)?;
+    Ok(transcript.finalize())
+}
+
+/// Runs the succinct verifier on one proof in-circuit and returns its
+/// assigned instances along with its accumulators.
+pub(crate) fn succinct_verify<'a>(
+    svk: &Svk,
+    loader: &Rc<Halo2Loader<'a>>,
+    protocol: &PlonkProtocol<G1Affine, Rc<Halo2Loader<'a>>>,
+    instances: &[Fr],
+    proof: &[u8],
+) -> Result<(Vec<AssignedValue<Fr>>, Vec<LoadedAccumulator<'a>>)> {
+    let instances = vec![instances.iter().map(|&instance| loader.assign_scalar(instance)).collect_vec()];
+    let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(loader, proof);
+    let proof = SuccinctVerifier::read_proof(svk, protocol, &instances, &mut transcript).map_err(snark_error)?;
+    let accumulators = SuccinctVerifier::verify(svk, protocol, &instances, &proof).map_err(snark_error)?;
+    let instances = instances.into_iter().flatten().map(|instance| instance.into_assigned()).collect();
+    Ok((instances, accumulators))
+}
+
+/// Folds `accumulators` in-circuit with the proof from [`fold_native`].
+pub(crate) fn fold<'a>(
+    loader: &Rc<Halo2Loader<'a>>,
+    accumulators: &[LoadedAccumulator<'a>],
+    as_proof: &[u8],
+) -> Result<LoadedAccumulator<'a>> {
+    let mut transcript = PoseidonTranscript::<Rc<Halo2Loader>, _>::new(loader, as_proof);
+    let proof = As::read_proof(&Default::default(), accumulators, &mut transcript).map_err(snark_error)?;
+    As::verify(&Default::default(), accumulators, &proof).map_err(snark_error)
+}
+
+/// The [`ACCUMULATOR_INSTANCES`] limbs an accumulator is exposed as.
+pub(crate) fn accumulator_limbs(accumulator: LoadedAccumulator) -> Vec<AssignedValue<Fr>> {
+    let KzgAccumulator { lhs, rhs } = accumulator;
+    let mut limbs = vec![];
+    for point in [lhs, rhs] {
+        let point = point.into_assigned();
+        limbs.extend(point.x().limbs().iter().chain(point.y().limbs()).copied());
+    }
+    limbs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::CircuitParams,
+        keys::CROP_CIRCUIT,
+        prover::keygen,
+        srs::SrsManager,
+        util,
+        verify::{verify_aggregate, VerifyError},
+    };
+
+    #[test]
+    fn test_aggregate() {
+        let (inner_k, k) = (13, 21);
+        let inner = CircuitSpec {
+            circuit: CircuitId::new(CROP_CIRCUIT),
+            width: 16,
+            height: 8,
+            channels: 3,
+            bit_depth: 8,
+            crop_width: 9,
+            crop_height: 5,
+            reveal_offset: true,
+            rows_per_leaf: None,
+            hiding: false,
+        };
+        // both key sets must come from the same setup
+        let params = SrsManager::new("params").dev_mode(true).load(k as u32).unwrap();
+        let mut inner_params = params.clone();
+        inner_params.downsize(inner_k as u32);
+
+        let mut inner_config = CircuitConfig::new(CircuitParams::new(inner_k, 12, Some(9)));
+        let circuit =
+            inner.circuit(CircuitBuilderStage::Keygen, &inner.blank(), (0, 0), None, &mut inner_config).unwrap();
+        let inner_pk = keygen(&inner_params, &circuit).unwrap();
+        inner_config.break_points = circuit.break_points();
+
+        let spec = AggregateSpec::new(inner.clone(), 2);
+        let blank = spec.blank_snarks(&inner_params, &inner_pk, &mut inner_config).unwrap();
+        let mut config = CircuitConfig::new(CircuitParams::new(k, k - 1, Some(20)));
+        let circuit = spec.circuit(CircuitBuilderStage::Keygen, &params, &blank, &mut config).unwrap();
+        let pk = keygen(&params, &circuit).unwrap();
+        config.break_points = circuit.break_points();
+
+        let image = util::generate_image(16, 8, 3, 8).unwrap();
+        let snarks = [(3, 2), (7, 0)].map(|offset| {
+            let (bundle, _) = inner
+                .prove::<ConfiguredCircuit<Fr>>(
+                    &inner_params,
+                    &inner_pk,
+                    &mut inner_config,
+                    &image,
+                    offset,
+                    None,
+                    Transcript::Poseidon,
+                )
+                .unwrap();
+            Snark::new(&inner_params, inner_pk.get_vk(), &inner, &bundle).unwrap()
+        });
+        let bundle = spec
+            .prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &snarks, Transcript::Blake2b)
+            .unwrap();
+        assert_eq!(bundle.instances.len(), spec.num_instance());
+        let verified = verify_aggregate(&params, pk.get_vk(), &inner, &bundle).unwrap();
+        assert_eq!(verified.len(), 2);
+        assert_eq!(&verified[1], snarks[1].statement());
+
+        // claiming a different offset for one of the proofs
+        let mut forged = bundle.clone();
+        forged.statements[0] = forged.statements[1].clone();
+        assert!(matches!(
+            verify_aggregate(&params, pk.get_vk(), &inner, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+
+        // only Poseidon transcripts can be replayed in the circuit
+        let (blake2b, _) = inner
+            .prove::<ConfiguredCircuit<Fr>>(
+                &inner_params,
+                &inner_pk,
+                &mut inner_config,
+                &image,
+                (3, 2),
+                None,
+                Transcript::Blake2b,
+            )
+            .unwrap();
+        assert!(matches!(
+            Snark::new(&inner_params, inner_pk.get_vk(), &inner, &blake2b),
+            Err(Error::UnsupportedTranscript(Transcript::Blake2b))
+        ));
+    }
+}
@@ -1,17 +0,0 @@
-// #![feature(test)]
-
-// extern crate zkimg;
-// use zkimg::circuit::run;
-
-// extern crate test;
-
-// use test::Bencher;
-
-// #[bench]
-// fn bench(b: &mut Bencher){
-//     b.iter(|| run());
-// }
-
-fn main(){
-    // run();
-}
\ No newline at end of file
@@ -0,0 +1,958 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use halo2_base::{
+    gates::builder::CircuitBuilderStage,
+    halo2_proofs::{
+        halo2curves::bn256::{Bn256, Fr},
+        poly::kzg::commitment::ParamsKZG,
+        SerdeFormat,
+    },
+};
+use image::{ColorType, DynamicImage, ImageBuffer};
+#[cfg(feature = "evm")]
+use zkimg::evm;
+use zkimg::{
+    aggregation::{AggregateSpec, Snark, AGGREGATE_CIRCUIT},
+    bundle::{
+        AggregateBundle, BundleHeader, CircuitId, HistoryBundle, ImageCommitment, ProofBundle, TiledBundle, Transcript,
+        Transformation,
+    },
+    config::{CircuitConfig, CircuitParams, ConfiguredCircuit},
+    estimate::{choose_k, estimate, SizeEstimate, Target},
+    history::{HistorySpec, HISTORY_CIRCUIT},
+    keys::{read_config, read_pk, read_vk, write_keys, CircuitSpec, Witness, CROP_CIRCUIT, MERKLE_CROP_CIRCUIT},
+    prover::keygen,
+    ptau::{convert, PtauFormat},
+    srs::SrsManager,
+    tile::{TileSpec, TiledSpec, TILED_CIRCUIT},
+    util::{self, Image, Signature},
+    verify::{verify, verify_aggregate, verify_history, verify_tiled},
+    Error, Result,
+};
+
+/// Key files are written in the format halo2 reads fastest.
+const KEY_FORMAT: SerdeFormat = SerdeFormat::RawBytes;
+/// Range of k the estimator tries when keygen is not given one.
+const MIN_K: usize = 10;
+const MAX_K: usize = 24;
+
+type Circuit = ConfiguredCircuit<Fr>;
+
+#[derive(Parser)]
+#[command(name = "zkimg", version, about = "Prove and verify edits of committed images")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a powers-of-tau transcript into params files
+    Setup {
+        #[arg(long)]
+        ptau: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Snarkjs)]
+        format: Format,
+        /// Comma separated sizes to write, e.g. 13,15
+        #[arg(long, value_delimiter = ',', required = true)]
+        k: Vec<u32>,
+        #[arg(long, default_value = "params")]
+        params: PathBuf,
+    },
+    /// Generate keys for one image size and crop size
+    Keygen {
+        #[command(flatten)]
+        shape: ShapeArgs,
+        /// Picked by the estimator if not given
+        #[arg(long, value_parser = parse_k)]
+        k: Option<usize>,
+        /// Defaults to k - 1
+        #[arg(long)]
+        lookup_bits: Option<usize>,
+        #[arg(long, default_value_t = 9)]
+        minimum_rows: usize,
+        /// When picking k, the smallest k with at most this many columns
+        /// instead of the k with the least proving work
+        #[arg(long, conflicts_with = "k")]
+        max_columns: Option<usize>,
+        /// Circuit parameters as JSON, instead of --k, --lookup-bits and --minimum-rows
+        #[arg(long, conflicts_with_all = ["k", "lookup_bits", "max_columns"])]
+        circuit_params: Option<PathBuf>,
+        #[arg(long, default_value = "keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Prove an edit of a PNG image
+    Prove {
+        #[arg(long)]
+        input: PathBuf,
+        /// crop:x,y,w,h
+        #[arg(long)]
+        edit: Edit,
+        /// Signature over the original image commitment, checked before proving
+        #[arg(long)]
+        sig: Option<PathBuf>,
+        /// Witness file from `commit --salt`, needed by hiding keys
+        #[arg(long)]
+        salt: Option<PathBuf>,
+        #[arg(long, default_value = "keys")]
+        keys: PathBuf,
+        /// Proof bundle, JSON if the name ends in .json and binary otherwise
+        #[arg(long, default_value = "proof.json")]
+        out: PathBuf,
+        /// Also write the edited image
+        #[arg(long)]
+        output: Option<PathBuf>,
+        /// keccak for proofs checked by the EVM verifier, poseidon for proofs
+        /// to aggregate
+        #[arg(long, value_enum, default_value_t = TranscriptKind::Blake2b)]
+        transcript: TranscriptKind,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Verify a proof bundle
+    Verify {
+        proof: PathBuf,
+        /// Signature over the original image commitment
+        #[arg(long)]
+        sig: Option<PathBuf>,
+        #[arg(long, default_value = "keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Generate keys for aggregating a fixed number of proofs made with one
+    /// key directory
+    AggregateKeygen {
+        /// Key directory of the proofs to aggregate
+        #[arg(long, default_value = "keys")]
+        inner: PathBuf,
+        #[arg(long)]
+        count: usize,
+        #[arg(long, default_value_t = 21, value_parser = parse_k)]
+        k: usize,
+        /// Defaults to k - 1
+        #[arg(long)]
+        lookup_bits: Option<usize>,
+        #[arg(long, default_value_t = 20)]
+        minimum_rows: usize,
+        #[arg(long, default_value = "aggregate-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Aggregate proof bundles made with --transcript poseidon into one proof
+    Aggregate {
+        #[arg(required = true)]
+        proofs: Vec<PathBuf>,
+        #[arg(long, default_value = "keys")]
+        inner: PathBuf,
+        #[arg(long, default_value = "aggregate-keys")]
+        keys: PathBuf,
+        #[arg(long, default_value = "aggregate.json")]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t = TranscriptKind::Blake2b)]
+        transcript: TranscriptKind,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Verify an aggregated proof bundle
+    VerifyAggregate {
+        proof: PathBuf,
+        #[arg(long, default_value = "aggregate-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Generate keys for edit chains whose edits are proven with one key
+    /// directory
+    HistoryKeygen {
+        /// Key directory of the edit proofs
+        #[arg(long, default_value = "keys")]
+        inner: PathBuf,
+        #[arg(long, default_value_t = 22, value_parser = parse_k)]
+        k: usize,
+        /// Defaults to k - 1
+        #[arg(long)]
+        lookup_bits: Option<usize>,
+        #[arg(long, default_value_t = 20)]
+        minimum_rows: usize,
+        #[arg(long, default_value = "history-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Add an edit, proven with --transcript poseidon, to an edit chain
+    History {
+        /// Proof bundle of the edit
+        proof: PathBuf,
+        /// Latest history bundle of the chain; starts a new chain if not given
+        #[arg(long)]
+        previous: Option<PathBuf>,
+        #[arg(long, default_value = "keys")]
+        inner: PathBuf,
+        #[arg(long, default_value = "history-keys")]
+        keys: PathBuf,
+        #[arg(long, default_value = "history.json")]
+        out: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Verify the latest proof of an edit chain
+    VerifyHistory {
+        proof: PathBuf,
+        /// Signature over the original image commitment
+        #[arg(long)]
+        sig: Option<PathBuf>,
+        #[arg(long, default_value = "history-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Generate keys for per-pixel edits of one tile, for images too large to
+    /// prove in one circuit
+    TileKeygen {
+        #[command(flatten)]
+        tile: TileArgs,
+        /// Picked by the estimator if not given
+        #[arg(long, value_parser = parse_k)]
+        k: Option<usize>,
+        /// Defaults to k - 1
+        #[arg(long)]
+        lookup_bits: Option<usize>,
+        #[arg(long, default_value_t = 9)]
+        minimum_rows: usize,
+        #[arg(long, default_value = "tile-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Generate keys joining the tile proofs of one image size into a proof
+    /// over the whole image
+    TiledKeygen {
+        /// Key directory of the tile proofs
+        #[arg(long, default_value = "tile-keys")]
+        inner: PathBuf,
+        #[arg(long)]
+        width: usize,
+        #[arg(long)]
+        height: usize,
+        #[arg(long, default_value_t = 22, value_parser = parse_k)]
+        k: usize,
+        /// Defaults to k - 1
+        #[arg(long)]
+        lookup_bits: Option<usize>,
+        #[arg(long, default_value_t = 20)]
+        minimum_rows: usize,
+        #[arg(long, default_value = "tiled-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Prove the edits of the tile keys on a PNG image, tile by tile
+    ProveTiled {
+        #[arg(long)]
+        input: PathBuf,
+        /// Signature over the original image's tiled commitment, checked
+        /// before proving
+        #[arg(long)]
+        sig: Option<PathBuf>,
+        #[arg(long, default_value = "tile-keys")]
+        inner: PathBuf,
+        #[arg(long, default_value = "tiled-keys")]
+        keys: PathBuf,
+        #[arg(long, default_value = "tiled.json")]
+        out: PathBuf,
+        /// Also write the edited image
+        #[arg(long)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = TranscriptKind::Blake2b)]
+        transcript: TranscriptKind,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Verify a tiled proof bundle
+    VerifyTiled {
+        proof: PathBuf,
+        /// Signature over the original image's tiled commitment
+        #[arg(long)]
+        sig: Option<PathBuf>,
+        #[arg(long, default_value = "tiled-keys")]
+        keys: PathBuf,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Print the commitment of a PNG image, which its owner signs
+    Commit {
+        input: PathBuf,
+        /// Commit tile by tile, for images proven with tiled keys
+        #[arg(long, requires = "tile_height")]
+        tile_width: Option<usize>,
+        #[arg(long, requires = "tile_width")]
+        tile_height: Option<usize>,
+        /// Print the hiding commitment with the salt in this witness file,
+        /// writing a fresh salt first if it does not exist
+        #[arg(long)]
+        salt: Option<PathBuf>,
+    },
+    /// Write a Solidity verifier for a key directory; its proofs need --transcript keccak
+    #[cfg(feature = "evm")]
+    EvmVerifier {
+        #[arg(long, default_value = "keys")]
+        keys: PathBuf,
+        #[arg(long, default_value = "Verifier.sol")]
+        out: PathBuf,
+        /// Compile with solc, write the deployment bytecode as hex and, with
+        /// --check, run it on a proof bundle in a local EVM
+        #[arg(long)]
+        bytecode: Option<PathBuf>,
+        #[arg(long, requires = "bytecode")]
+        check: Option<PathBuf>,
+        #[command(flatten)]
+        srs: SrsArgs,
+    },
+    /// Print the circuit layout for each k
+    Estimate {
+        #[command(flatten)]
+        shape: ShapeArgs,
+        #[arg(long, default_value_t = MIN_K, value_parser = parse_k)]
+        min_k: usize,
+        #[arg(long, default_value_t = MAX_K, value_parser = parse_k)]
+        max_k: usize,
+        #[arg(long, default_value_t = 9)]
+        minimum_rows: usize,
+    },
+    /// Print a proof bundle or a key directory
+    Inspect { path: PathBuf },
+}
+
+/// The image shape and edit a key covers.
+#[derive(Args)]
+struct ShapeArgs {
+    #[arg(long)]
+    width: usize,
+    #[arg(long)]
+    height: usize,
+    #[arg(long, default_value_t = 3)]
+    channels: usize,
+    #[arg(long, default_value_t = 8)]
+    bit_depth: usize,
+    /// crop:w,h; the offset is chosen when proving
+    #[arg(long)]
+    edit: Edit,
+    /// Keep the crop offset out of the public instances
+    #[arg(long)]
+    hide_offset: bool,
+    /// Commit the original as a Merkle tree of bands of this many rows, so
+    /// proving only opens the bands the crop touches
+    #[arg(long)]
+    rows_per_leaf: Option<usize>,
+    /// Blind the original's commitment with a salt only the prover holds, see
+    /// `commit --salt`
+    #[arg(long)]
+    hiding: bool,
+}
+
+impl ShapeArgs {
+    fn spec(&self) -> CircuitSpec {
+        let Edit::Crop { width: crop_width, height: crop_height, .. } = self.edit;
+        CircuitSpec {
+            circuit: CircuitId::new(if self.rows_per_leaf.is_some() { MERKLE_CROP_CIRCUIT } else { CROP_CIRCUIT }),
+            width: self.width,
+            height: self.height,
+            channels: self.channels,
+            bit_depth: self.bit_depth,
+            crop_width,
+            crop_height,
+            reveal_offset: !self.hide_offset,
+            rows_per_leaf: self.rows_per_leaf,
+            hiding: self.hiding,
+        }
+    }
+}
+
+/// The tile shape and per-pixel edits tile keys cover.
+#[derive(Args)]
+struct TileArgs {
+    #[arg(long)]
+    tile_width: usize,
+    #[arg(long)]
+    tile_height: usize,
+    #[arg(long, default_value_t = 3)]
+    channels: usize,
+    #[arg(long, default_value_t = 8)]
+    bit_depth: usize,
+    /// flatten-alpha:r,g,b or convert-depth:bits, applied in the order given
+    #[arg(long = "edit")]
+    edits: Vec<PixelEdit>,
+}
+
+impl TileArgs {
+    fn spec(&self) -> Result<TileSpec> {
+        let mut bit_depth = self.bit_depth;
+        let edits = self
+            .edits
+            .iter()
+            .map(|edit| match edit {
+                PixelEdit::FlattenAlpha { background } => {
+                    Transformation::FlattenAlpha { background: background.clone() }
+                }
+                PixelEdit::ConvertDepth { to_bits } => {
+                    let from_bits = std::mem::replace(&mut bit_depth, *to_bits);
+                    Transformation::ConvertDepth { from_bits, to_bits: *to_bits }
+                }
+            })
+            .collect();
+        TileSpec::new(self.tile_width, self.tile_height, self.channels, self.bit_depth, edits)
+    }
+}
+
+#[derive(Args)]
+struct SrsArgs {
+    #[arg(long, default_value = "params")]
+    params: PathBuf,
+    /// Fall back to an insecure local setup if no params file is large enough
+    #[arg(long)]
+    dev: bool,
+}
+
+impl SrsArgs {
+    fn load(&self, k: usize) -> Result<ParamsKZG<Bn256>> {
+        SrsManager::new(&self.params).dev_mode(self.dev).load(k as u32)
+    }
+
+    /// Params for the aggregation keys and, downsized from the same setup,
+    /// for the inner keys. A dev setup is random, so loading the two
+    /// separately would not match.
+    fn load_pair(&self, k: usize, inner_k: usize) -> Result<(ParamsKZG<Bn256>, ParamsKZG<Bn256>)> {
+        let params = self.load(k)?;
+        let mut inner = params.clone();
+        inner.downsize(inner_k as u32);
+        Ok((params, inner))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Snarkjs,
+    Challenge,
+}
+
+impl From<Format> for PtauFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Snarkjs => PtauFormat::Snarkjs,
+            Format::Challenge => PtauFormat::Challenge,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TranscriptKind {
+    Blake2b,
+    Keccak,
+    Poseidon,
+}
+
+impl From<TranscriptKind> for Transcript {
+    fn from(kind: TranscriptKind) -> Self {
+        match kind {
+            TranscriptKind::Blake2b => Transcript::Blake2b,
+            TranscriptKind::Keccak => Transcript::Keccak,
+            TranscriptKind::Poseidon => Transcript::Poseidon,
+        }
+    }
+}
+
+/// `crop:x,y,w,h`, or `crop:w,h` where the offset does not matter.
+#[derive(Clone, Copy, Debug)]
+enum Edit {
+    Crop { offset: Option<(usize, usize)>, width: usize, height: usize },
+}
+
+impl FromStr for Edit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, args) = s.split_once(':').ok_or("expected <edit>:<args>, e.g. crop:0,0,64,64")?;
+        let args = args
+            .split(',')
+            .map(|arg| arg.trim().parse::<usize>().map_err(|e| format!("{arg:?}: {e}")))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        match (kind, &args[..]) {
+            ("crop", &[x, y, width, height]) => Ok(Edit::Crop { offset: Some((x, y)), width, height }),
+            ("crop", &[width, height]) => Ok(Edit::Crop { offset: None, width, height }),
+            ("crop", _) => Err("crop takes x,y,w,h".to_string()),
+            _ => Err(format!("unknown edit {kind:?}, supported: crop")),
+        }
+    }
+}
+
+/// `flatten-alpha:r,g,b` or `convert-depth:bits`.
+#[derive(Clone, Debug)]
+enum PixelEdit {
+    FlattenAlpha { background: Vec<u64> },
+    ConvertDepth { to_bits: usize },
+}
+
+impl FromStr for PixelEdit {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, args) = s.split_once(':').ok_or("expected <edit>:<args>, e.g. convert-depth:8")?;
+        let args = args
+            .split(',')
+            .map(|arg| arg.trim().parse::<u64>().map_err(|e| format!("{arg:?}: {e}")))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        match (kind, &args[..]) {
+            ("flatten-alpha", _) => Ok(PixelEdit::FlattenAlpha { background: args }),
+            ("convert-depth", &[to_bits]) => Ok(PixelEdit::ConvertDepth { to_bits: to_bits as usize }),
+            ("convert-depth", _) => Err("convert-depth takes the new bit depth".to_string()),
+            _ => Err(format!("unknown edit {kind:?}, supported: flatten-alpha, convert-depth")),
+        }
+    }
+}
+
+/// A circuit needs rows for its lookup table, and `lookup_bits` defaults to
+/// k - 1, so k starts at 1.
+fn parse_k(arg: &str) -> std::result::Result<usize, String> {
+    match arg.parse::<usize>().map_err(|e| format!("{arg:?}: {e}"))? {
+        0 => Err("k must be at least 1".to_string()),
+        k => Ok(k),
+    }
+}
+
+fn check_signature(path: &Path, commitment: Fr) -> Result<()> {
+    Signature::from_json(&fs::read_to_string(path)?)?.verify(commitment)
+}
+
+/// Reads a PNG as interleaved channel values along with
+/// `(width, height, channels, bit_depth)`.
+fn read_png(path: &Path) -> Result<(Image, usize, usize, usize, usize)> {
+    let img = image::open(path).map_err(|e| Error::Encoding(format!("{path:?}: {e}")))?;
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let widen = |values: Vec<u8>| values.into_iter().map(u64::from).collect::<Image>();
+    let widen16 = |values: Vec<u16>| values.into_iter().map(u64::from).collect::<Image>();
+    let (pixels, channels, bit_depth) = match img.color() {
+        ColorType::L8 => (widen(img.into_luma8().into_raw()), 1, 8),
+        ColorType::La8 => (widen(img.into_luma_alpha8().into_raw()), 2, 8),
+        ColorType::Rgb8 => (widen(img.into_rgb8().into_raw()), 3, 8),
+        ColorType::Rgba8 => (widen(img.into_rgba8().into_raw()), 4, 8),
+        ColorType::L16 => (widen16(img.into_luma16().into_raw()), 1, 16),
+        ColorType::La16 => (widen16(img.into_luma_alpha16().into_raw()), 2, 16),
+        ColorType::Rgb16 => (widen16(img.into_rgb16().into_raw()), 3, 16),
+        ColorType::Rgba16 => (widen16(img.into_rgba16().into_raw()), 4, 16),
+        color => return Err(Error::Encoding(format!("{path:?}: unsupported colour type {color:?}"))),
+    };
+    Ok((pixels, width, height, channels, bit_depth))
+}
+
+fn write_png(path: &Path, image: &Image, width: usize, height: usize, channels: usize, bit_depth: usize) -> Result<()> {
+    let (w, h) = (width as u32, height as u32);
+    let narrow = || image.iter().map(|&v| v as u8).collect::<Vec<_>>();
+    let narrow16 = || image.iter().map(|&v| v as u16).collect::<Vec<_>>();
+    let img = match (channels, bit_depth) {
+        (1, 8) => ImageBuffer::from_raw(w, h, narrow()).map(DynamicImage::ImageLuma8),
+        (2, 8) => ImageBuffer::from_raw(w, h, narrow()).map(DynamicImage::ImageLumaA8),
+        (3, 8) => ImageBuffer::from_raw(w, h, narrow()).map(DynamicImage::ImageRgb8),
+        (4, 8) => ImageBuffer::from_raw(w, h, narrow()).map(DynamicImage::ImageRgba8),
+        (1, 16) => ImageBuffer::from_raw(w, h, narrow16()).map(DynamicImage::ImageLuma16),
+        (2, 16) => ImageBuffer::from_raw(w, h, narrow16()).map(DynamicImage::ImageLumaA16),
+        (3, 16) => ImageBuffer::from_raw(w, h, narrow16()).map(DynamicImage::ImageRgb16),
+        (4, 16) => ImageBuffer::from_raw(w, h, narrow16()).map(DynamicImage::ImageRgba16),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        Error::InvalidDimensions(format!("cannot write {channels} channel {bit_depth}-bit image as PNG"))
+    })?;
+    img.save(path).map_err(|e| Error::Encoding(format!("{path:?}: {e}")))
+}
+
+fn print_estimate(size: &SizeEstimate) {
+    println!(
+        "k = {:>2}: {} advice, {} lookup, {} fixed columns ({} prover cells)",
+        size.params.k,
+        size.num_advice,
+        size.num_lookup_advice,
+        size.num_fixed,
+        size.prover_cells()
+    );
+}
+
+fn print_image(name: &str, image: &ImageCommitment) {
+    println!(
+        "{name}: {}x{}x{} {}-bit, commitment {:?}",
+        image.width, image.height, image.channels, image.bit_depth, image.commitment
+    );
+}
+
+fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Setup { ptau, format, k, params } => {
+            for path in convert(ptau, format.into(), params, &k)? {
+                println!("wrote {}", path.display());
+            }
+        }
+        Command::Keygen { shape, k, lookup_bits, minimum_rows, max_columns, circuit_params, keys, srs } => {
+            let spec = shape.spec();
+            let blank = spec.blank();
+            let circuit_params = match (circuit_params, k) {
+                (Some(path), _) => CircuitParams::read(path)?,
+                (None, Some(k)) => CircuitParams::new(k, lookup_bits.unwrap_or(k - 1), Some(minimum_rows)),
+                (None, None) => {
+                    let target = max_columns.map_or(Target::MinProvingWork, Target::MaxColumns);
+                    let size = choose_k(MIN_K..=MAX_K, Some(minimum_rows), target, |builder, params| {
+                        spec.assign(builder, &blank, (0, 0), None, params).map(|_| ())
+                    })?;
+                    print_estimate(&size);
+                    size.params
+                }
+            };
+            let mut config = CircuitConfig::new(circuit_params);
+            let params = srs.load(config.params.k)?;
+
+            let circuit = spec.circuit(CircuitBuilderStage::Keygen, &blank, (0, 0), None, &mut config)?;
+            let pk = keygen(&params, &circuit)?;
+            config.break_points = circuit.break_points();
+
+            write_keys(&keys, &pk, &config, KEY_FORMAT)?;
+            spec.write(&keys)?;
+            println!(
+                "wrote keys for {}x{} -> {}x{} to {}",
+                spec.width,
+                spec.height,
+                spec.crop_width,
+                spec.crop_height,
+                keys.display()
+            );
+        }
+        Command::Prove { input, edit, sig, salt, keys, out, output, transcript, srs } => {
+            let spec = CircuitSpec::read(&keys)?;
+            let Edit::Crop { offset, width, height } = edit;
+            let (x, y) = offset.ok_or_else(|| Error::InvalidDimensions("prove needs crop:x,y,w,h".to_string()))?;
+            if (width, height) != (spec.crop_width, spec.crop_height) {
+                return Err(Error::InvalidDimensions(format!(
+                    "keys are for a {}x{} crop, got {width}x{height}",
+                    spec.crop_width, spec.crop_height
+                )));
+            }
+
+            let (image, image_width, image_height, channels, bit_depth) = read_png(&input)?;
+            spec.check_shape(image_width, image_height, channels, bit_depth)?;
+            let salt = salt.map(Witness::read).transpose()?.map(|witness| witness.salt);
+            if let Some(sig) = &sig {
+                check_signature(sig, spec.original_commitment(&image, salt)?.commitment)?;
+            }
+
+            let (pk, mut config) = read_pk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            let (bundle, cropped) =
+                spec.prove::<Circuit>(&params, &pk, &mut config, &image, (x, y), salt, transcript.into())?;
+            bundle.write(&out)?;
+            println!("wrote {}", out.display());
+            if let Some(output) = output {
+                write_png(&output, &cropped, width, height, spec.channels, spec.bit_depth)?;
+                println!("wrote {}", output.display());
+            }
+        }
+        Command::Verify { proof, sig, keys, srs } => {
+            let bundle = ProofBundle::read(&proof)?;
+            let spec = CircuitSpec::read(&keys)?;
+            let (vk, config) = read_vk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            let verified = verify(&params, &vk, &spec, &bundle)?;
+            if let Some(sig) = &sig {
+                check_signature(sig, verified.original.commitment)?;
+            }
+            println!("valid: {:?}", verified.transformation);
+            if sig.is_some() {
+                println!("original image signature: valid");
+            }
+        }
+        Command::AggregateKeygen { inner, count, k, lookup_bits, minimum_rows, keys, srs } => {
+            let spec = AggregateSpec::new(CircuitSpec::read(&inner)?, count);
+            let (inner_pk, mut inner_config) = read_pk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(k, inner_config.params.k)?;
+            let blank = spec.blank_snarks(&inner_params, &inner_pk, &mut inner_config)?;
+
+            let mut config =
+                CircuitConfig::new(CircuitParams::new(k, lookup_bits.unwrap_or(k - 1), Some(minimum_rows)));
+            let circuit = spec.circuit(CircuitBuilderStage::Keygen, &params, &blank, &mut config)?;
+            let pk = keygen(&params, &circuit)?;
+            config.break_points = circuit.break_points();
+
+            write_keys(&keys, &pk, &config, KEY_FORMAT)?;
+            spec.write(&keys)?;
+            println!("wrote keys aggregating {count} proofs of {} to {}", inner.display(), keys.display());
+        }
+        Command::Aggregate { proofs, inner, keys, out, transcript, srs } => {
+            let spec = AggregateSpec::read(&keys)?;
+            let (inner_vk, inner_config) = read_vk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (pk, mut config) = read_pk::<Circuit>(&keys, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(config.params.k, inner_config.params.k)?;
+            let snarks = proofs
+                .iter()
+                .map(|path| Snark::new(&inner_params, &inner_vk, &spec.inner, &ProofBundle::read(path)?))
+                .collect::<Result<Vec<_>>>()?;
+            let bundle = spec.prove::<Circuit>(&params, &pk, &mut config, &snarks, transcript.into())?;
+            bundle.write(&out)?;
+            println!("wrote {}", out.display());
+        }
+        Command::VerifyAggregate { proof, keys, srs } => {
+            let bundle = AggregateBundle::read(&proof)?;
+            let spec = AggregateSpec::read(&keys)?;
+            let (vk, config) = read_vk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            for (i, verified) in verify_aggregate(&params, &vk, &spec.inner, &bundle)?.iter().enumerate() {
+                println!("valid {i}: {:?} of {:?}", verified.transformation, verified.original.commitment);
+            }
+        }
+        Command::HistoryKeygen { inner, k, lookup_bits, minimum_rows, keys, srs } => {
+            let spec = HistorySpec::new(CircuitSpec::read(&inner)?);
+            let (inner_pk, mut inner_config) = read_pk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(k, inner_config.params.k)?;
+            let mut config =
+                CircuitConfig::new(CircuitParams::new(k, lookup_bits.unwrap_or(k - 1), Some(minimum_rows)));
+            let pk = spec.keygen(&params, &inner_params, &inner_pk, &mut inner_config, &mut config)?;
+
+            write_keys(&keys, &pk, &config, KEY_FORMAT)?;
+            spec.write(&keys)?;
+            println!("wrote history keys for edits with {} to {}", inner.display(), keys.display());
+        }
+        Command::History { proof, previous, inner, keys, out, srs } => {
+            let spec = HistorySpec::read(&keys)?;
+            let (inner_vk, inner_config) = read_vk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (pk, mut config) = read_pk::<Circuit>(&keys, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(config.params.k, inner_config.params.k)?;
+            let edit = Snark::new(&inner_params, &inner_vk, &spec.inner, &ProofBundle::read(&proof)?)?;
+            let previous = previous.map(HistoryBundle::read).transpose()?;
+            let bundle = spec.prove::<Circuit>(&params, &pk, &mut config, &edit, previous.as_ref())?;
+            bundle.write(&out)?;
+            println!("wrote {} ({} edits)", out.display(), bundle.edits);
+        }
+        Command::VerifyHistory { proof, sig, keys, srs } => {
+            let bundle = HistoryBundle::read(&proof)?;
+            let spec = HistorySpec::read(&keys)?;
+            let (vk, config) = read_vk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            verify_history(&params, &vk, &spec.inner, &bundle)?;
+            if let Some(sig) = &sig {
+                check_signature(sig, bundle.original.commitment)?;
+            }
+            println!("valid: {} edits, latest {:?}", bundle.edits, bundle.latest.commitment);
+            if sig.is_some() {
+                println!("original image signature: valid");
+            }
+        }
+        Command::TileKeygen { tile, k, lookup_bits, minimum_rows, keys, srs } => {
+            let spec = tile.spec()?;
+            let blank = spec.blank();
+            let circuit_params = match k {
+                Some(k) => CircuitParams::new(k, lookup_bits.unwrap_or(k - 1), Some(minimum_rows)),
+                None => {
+                    let size = choose_k(MIN_K..=MAX_K, Some(minimum_rows), Target::MinProvingWork, |builder, params| {
+                        spec.assign(builder, &blank, params).map(|_| ())
+                    })?;
+                    print_estimate(&size);
+                    size.params
+                }
+            };
+            let mut config = CircuitConfig::new(circuit_params);
+            let params = srs.load(config.params.k)?;
+
+            let circuit = spec.circuit(CircuitBuilderStage::Keygen, &blank, &mut config)?;
+            let pk = keygen(&params, &circuit)?;
+            config.break_points = circuit.break_points();
+
+            write_keys(&keys, &pk, &config, KEY_FORMAT)?;
+            spec.write(&keys)?;
+            println!("wrote keys for {}x{} tiles to {}", spec.tile_width, spec.tile_height, keys.display());
+        }
+        Command::TiledKeygen { inner, width, height, k, lookup_bits, minimum_rows, keys, srs } => {
+            let spec = TiledSpec::new(TileSpec::read(&inner)?, width, height)?;
+            let (inner_pk, mut inner_config) = read_pk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(k, inner_config.params.k)?;
+            let blank = spec.blank_proofs(&inner_params, &inner_pk, &mut inner_config)?;
+
+            let mut config =
+                CircuitConfig::new(CircuitParams::new(k, lookup_bits.unwrap_or(k - 1), Some(minimum_rows)));
+            let circuit = spec.circuit(CircuitBuilderStage::Keygen, &params, &blank, &mut config)?;
+            let pk = keygen(&params, &circuit)?;
+            config.break_points = circuit.break_points();
+
+            write_keys(&keys, &pk, &config, KEY_FORMAT)?;
+            spec.write(&keys)?;
+            println!("wrote keys for {width}x{height} images in {} tiles to {}", spec.num_tiles(), keys.display());
+        }
+        Command::ProveTiled { input, sig, inner, keys, out, output, transcript, srs } => {
+            let spec = TiledSpec::read(&keys)?;
+            let tile = &spec.tile;
+            let (image, width, height, channels, bit_depth) = read_png(&input)?;
+            if (width, height, channels, bit_depth) != (spec.width, spec.height, tile.channels, tile.bit_depth) {
+                return Err(Error::InvalidDimensions(format!(
+                    "keys are for {}x{} images with {} {}-bit channels, got {width}x{height} with {channels} {bit_depth}-bit channels",
+                    spec.width, spec.height, tile.channels, tile.bit_depth
+                )));
+            }
+            if let Some(sig) = &sig {
+                let (tile_width, tile_height) = (tile.tile_width, tile.tile_height);
+                let commitment =
+                    util::commit_tiled_image(&image, width, height, channels, bit_depth, tile_width, tile_height)?;
+                check_signature(sig, commitment)?;
+            }
+
+            let (inner_pk, mut inner_config) = read_pk::<Circuit>(&inner, KEY_FORMAT)?;
+            let (pk, mut config) = read_pk::<Circuit>(&keys, KEY_FORMAT)?;
+            let (params, inner_params) = srs.load_pair(config.params.k, inner_config.params.k)?;
+            let proofs = spec.prove_tiles::<Circuit>(&inner_params, &inner_pk, &mut inner_config, &image)?;
+            let bundle = spec.prove::<Circuit>(&params, &pk, &mut config, &proofs, transcript.into())?;
+            bundle.write(&out)?;
+            println!("wrote {} ({} tiles)", out.display(), proofs.len());
+            if let Some(output) = output {
+                let (edited, _, _, channels, bit_depth) =
+                    util::apply_edits(&image, width, height, channels, bit_depth, &tile.edits()?)?;
+                write_png(&output, &edited, width, height, channels, bit_depth)?;
+                println!("wrote {}", output.display());
+            }
+        }
+        Command::VerifyTiled { proof, sig, keys, srs } => {
+            let bundle = TiledBundle::read(&proof)?;
+            let spec = TiledSpec::read(&keys)?;
+            let (vk, config) = read_vk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            verify_tiled(&params, &vk, &spec, &bundle)?;
+            if let Some(sig) = &sig {
+                check_signature(sig, bundle.original.commitment)?;
+            }
+            println!("valid: {:?} on {}x{} tiles", bundle.edits, bundle.tile_width, bundle.tile_height);
+            if sig.is_some() {
+                println!("original image signature: valid");
+            }
+        }
+        Command::Commit { input, tile_width, tile_height, salt } => {
+            let (image, width, height, channels, bit_depth) = read_png(&input)?;
+            let mut commitment: Fr = match tile_width.zip(tile_height) {
+                Some((tile_width, tile_height)) => {
+                    util::commit_tiled_image(&image, width, height, channels, bit_depth, tile_width, tile_height)?
+                }
+                None => util::commit_image(&image, width, height, channels, bit_depth)?,
+            };
+            if let Some(path) = salt {
+                let witness = if path.exists() {
+                    Witness::read(&path)?
+                } else {
+                    let witness = Witness::random();
+                    witness.write(&path)?;
+                    println!("wrote {}", path.display());
+                    witness
+                };
+                commitment = util::hiding_commitment(commitment, witness.salt);
+            }
+            println!("{width}x{height}x{channels} {bit_depth}-bit, commitment {commitment:?}");
+        }
+        #[cfg(feature = "evm")]
+        Command::EvmVerifier { keys, out, bytecode, check, srs } => {
+            let spec = CircuitSpec::read(&keys)?;
+            let (vk, config) = read_vk::<Circuit>(&keys, KEY_FORMAT)?;
+            let params = srs.load(config.params.k)?;
+            let code = evm::gen_solidity_verifier(&params, &vk, spec.num_instance())?;
+            fs::write(&out, &code)?;
+            println!("wrote {}", out.display());
+            if let Some(bytecode) = bytecode {
+                let deployment_code = evm::compile_solidity(&code)?;
+                fs::write(&bytecode, hex::encode(&deployment_code))?;
+                println!("wrote {} ({} bytes)", bytecode.display(), deployment_code.len());
+                if let Some(check) = check {
+                    let bundle = ProofBundle::read(&check)?;
+                    if bundle.transcript != Transcript::Keccak {
+                        return Err(Error::UnsupportedTranscript(bundle.transcript));
+                    }
+                    let gas = evm::evm_verify(deployment_code, &bundle.instances, &bundle.proof)?;
+                    println!("valid on the EVM, {gas} gas");
+                }
+            }
+        }
+        Command::Estimate { shape, min_k, max_k, minimum_rows } => {
+            let spec = shape.spec();
+            let blank = spec.blank();
+            for k in min_k..=max_k {
+                let size = estimate(CircuitParams::new(k, k - 1, Some(minimum_rows)), |builder, params| {
+                    spec.assign(builder, &blank, (0, 0), None, params).map(|_| ())
+                })?;
+                print_estimate(&size);
+            }
+        }
+        Command::Inspect { path } => {
+            if path.is_dir() {
+                println!("{}", serde_json::to_string_pretty(&CircuitSpec::read(&path)?)?);
+                let config = read_config(&path)?;
+                println!("{}", serde_json::to_string_pretty(&config.params)?);
+                println!("{}", serde_json::to_string_pretty(&config.gate_params)?);
+            } else {
+                let header = BundleHeader::read(&path)?;
+                println!("version: {}", header.version);
+                println!("circuit: {} {}", header.circuit.name, header.circuit.version);
+                let (instances, proof) = match header.circuit.name.as_str() {
+                    AGGREGATE_CIRCUIT => {
+                        let bundle = AggregateBundle::read(&path)?;
+                        println!("vk: {:?}", bundle.vk_hash);
+                        println!("transcript: {:?}", bundle.transcript);
+                        for statement in &bundle.statements {
+                            println!("statement: {} {:?}", statement.circuit.name, statement.transformation);
+                            print_image("  original", &statement.original);
+                            print_image("  result", &statement.result);
+                        }
+                        (bundle.instances.len(), bundle.proof.len())
+                    }
+                    HISTORY_CIRCUIT => {
+                        let bundle = HistoryBundle::read(&path)?;
+                        println!("vk: {:?}", bundle.vk_hash);
+                        println!("edits: {}", bundle.edits);
+                        print_image("original", &bundle.original);
+                        print_image("latest", &bundle.latest);
+                        (bundle.instances.len(), bundle.proof.len())
+                    }
+                    TILED_CIRCUIT => {
+                        let bundle = TiledBundle::read(&path)?;
+                        println!("vk: {:?}", bundle.vk_hash);
+                        println!("transcript: {:?}", bundle.transcript);
+                        println!("edits: {:?} on {}x{} tiles", bundle.edits, bundle.tile_width, bundle.tile_height);
+                        print_image("original", &bundle.original);
+                        print_image("result", &bundle.result);
+                        (bundle.instances.len(), bundle.proof.len())
+                    }
+                    _ => {
+                        let bundle = ProofBundle::read(&path)?;
+                        println!("vk: {:?}", bundle.vk_hash);
+                        println!("transcript: {:?}", bundle.transcript);
+                        println!("edit: {:?}", bundle.transformation);
+                        print_image("original", &bundle.original);
+                        print_image("result", &bundle.result);
+                        (bundle.instances.len(), bundle.proof.len())
+                    }
+                };
+                println!("instances: {instances}");
+                println!("proof: {proof} bytes");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    env_logger::init();
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
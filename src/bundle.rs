@@ -0,0 +1,528 @@
+use std::{fs, path::Path};
+
+use halo2_base::halo2_proofs::{
+    halo2curves::bn256::{Fr, G1Affine},
+    plonk::VerifyingKey,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Bumped whenever the bundle layout changes incompatibly.
+pub const BUNDLE_VERSION: u32 = 3;
+
+/// Which circuit a proof is for. `version` is the zkimg version that defined
+/// the circuit, since a changed gadget changes the verifying key.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitId {
+    pub name: String,
+    pub version: String,
+}
+
+impl CircuitId {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), version: env!("CARGO_PKG_VERSION").to_string() }
+    }
+}
+
+/// The edit a proof attests to. Externally tagged, since bincode cannot read
+/// back internally tagged enums.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transformation {
+    /// `x` and `y` are `None` when the crop offset is kept private.
+    Crop { x: Option<usize>, y: Option<usize>, width: usize, height: usize },
+    FlattenAlpha { background: Vec<u64> },
+    ConvertDepth { from_bits: usize, to_bits: usize },
+}
+
+/// The Fiat-Shamir transcript a proof was made with. Keccak is the one the
+/// generated EVM verifier replays and Poseidon the one a circuit can replay
+/// cheaply, for aggregation and recursion.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transcript {
+    #[default]
+    Blake2b,
+    Keccak,
+    Poseidon,
+}
+
+/// Shape of an image and its commitment from [`crate::util::commit_image`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImageCommitment {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub bit_depth: usize,
+    #[serde(with = "hex_fr")]
+    pub commitment: Fr,
+}
+
+/// What a proof establishes: an edit taking the image behind `original` to the
+/// one behind `result`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Statement {
+    pub circuit: CircuitId,
+    pub transformation: Transformation,
+    pub original: ImageCommitment,
+    pub result: ImageCommitment,
+}
+
+/// A proof together with everything needed to check it against a verifying
+/// key and to tell what it proves.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofBundle {
+    pub version: u32,
+    pub circuit: CircuitId,
+    /// `VerifyingKey::transcript_repr` of the key the proof was made for.
+    #[serde(with = "hex_fr")]
+    pub vk_hash: Fr,
+    pub transcript: Transcript,
+    pub transformation: Transformation,
+    pub original: ImageCommitment,
+    pub result: ImageCommitment,
+    /// Public instances of the single instance column.
+    #[serde(with = "hex_fr_vec")]
+    pub instances: Vec<Fr>,
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl ProofBundle {
+    pub fn new(
+        circuit: CircuitId,
+        vk: &VerifyingKey<G1Affine>,
+        transcript: Transcript,
+        transformation: Transformation,
+        original: ImageCommitment,
+        result: ImageCommitment,
+        instances: Vec<Fr>,
+        proof: Vec<u8>,
+    ) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            circuit,
+            vk_hash: vk.transcript_repr(),
+            transcript,
+            transformation,
+            original,
+            result,
+            instances,
+            proof,
+        }
+    }
+
+    pub fn statement(&self) -> Statement {
+        Statement {
+            circuit: self.circuit.clone(),
+            transformation: self.transformation.clone(),
+            original: self.original.clone(),
+            result: self.result.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Compact binary encoding; field elements and the proof are raw bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Writes JSON if `path` ends in `.json` and the binary encoding otherwise.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_file(self, path.as_ref())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+}
+
+/// Many proofs of one circuit folded into a single proof of an aggregation
+/// circuit. Its public instances are the KZG accumulator of the folded proofs
+/// followed by the instances of each statement, in order; the folded proofs
+/// themselves are not kept.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateBundle {
+    pub version: u32,
+    /// The aggregation circuit.
+    pub circuit: CircuitId,
+    /// `VerifyingKey::transcript_repr` of the aggregation key.
+    #[serde(with = "hex_fr")]
+    pub vk_hash: Fr,
+    pub transcript: Transcript,
+    pub statements: Vec<Statement>,
+    #[serde(with = "hex_fr_vec")]
+    pub instances: Vec<Fr>,
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl AggregateBundle {
+    pub fn new(
+        circuit: CircuitId,
+        vk: &VerifyingKey<G1Affine>,
+        transcript: Transcript,
+        statements: Vec<Statement>,
+        instances: Vec<Fr>,
+        proof: Vec<u8>,
+    ) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            circuit,
+            vk_hash: vk.transcript_repr(),
+            transcript,
+            statements,
+            instances,
+            proof,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Same file conventions as [`ProofBundle::write`].
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_file(self, path.as_ref())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+}
+
+/// The latest proof of a chain of edits. It verifies the chain's previous
+/// proof in-circuit, so it attests to all `edits` edits from `original` to
+/// `latest` while the images in between stay private. History proofs are
+/// always made with [`Transcript::Poseidon`] so the next edit can verify them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryBundle {
+    pub version: u32,
+    /// The history circuit.
+    pub circuit: CircuitId,
+    /// `VerifyingKey::transcript_repr` of the history key.
+    #[serde(with = "hex_fr")]
+    pub vk_hash: Fr,
+    pub edits: usize,
+    pub original: ImageCommitment,
+    pub latest: ImageCommitment,
+    #[serde(with = "hex_fr_vec")]
+    pub instances: Vec<Fr>,
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl HistoryBundle {
+    pub fn new(
+        circuit: CircuitId,
+        vk: &VerifyingKey<G1Affine>,
+        edits: usize,
+        original: ImageCommitment,
+        latest: ImageCommitment,
+        instances: Vec<Fr>,
+        proof: Vec<u8>,
+    ) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            circuit,
+            vk_hash: vk.transcript_repr(),
+            edits,
+            original,
+            latest,
+            instances,
+            proof,
+        }
+    }
+
+    /// Same file conventions as [`ProofBundle::write`].
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_file(self, path.as_ref())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+}
+
+/// A per-pixel edit of an image too large for one circuit, proven tile by
+/// tile. The tile proofs are folded into one proof of the tiled circuit, so
+/// the bundle is the size of a single proof. `original` and `result` hold
+/// [`crate::util::commit_tiled_image`] commitments over tiles of
+/// `tile_width` by `tile_height`, which is what the original's owner signs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TiledBundle {
+    pub version: u32,
+    /// The tiled circuit.
+    pub circuit: CircuitId,
+    /// `VerifyingKey::transcript_repr` of the tiled key.
+    #[serde(with = "hex_fr")]
+    pub vk_hash: Fr,
+    pub transcript: Transcript,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    /// The edits applied to every tile, in order.
+    pub edits: Vec<Transformation>,
+    pub original: ImageCommitment,
+    pub result: ImageCommitment,
+    #[serde(with = "hex_fr_vec")]
+    pub instances: Vec<Fr>,
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+}
+
+impl TiledBundle {
+    pub fn new(
+        circuit: CircuitId,
+        vk: &VerifyingKey<G1Affine>,
+        transcript: Transcript,
+        (tile_width, tile_height): (usize, usize),
+        edits: Vec<Transformation>,
+        original: ImageCommitment,
+        result: ImageCommitment,
+        instances: Vec<Fr>,
+        proof: Vec<u8>,
+    ) -> Self {
+        Self {
+            version: BUNDLE_VERSION,
+            circuit,
+            vk_hash: vk.transcript_repr(),
+            transcript,
+            tile_width,
+            tile_height,
+            edits,
+            original,
+            result,
+            instances,
+            proof,
+        }
+    }
+
+    /// Same file conventions as [`ProofBundle::write`].
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        write_file(self, path.as_ref())
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+}
+
+/// The fields every bundle starts with, to tell which kind a file holds
+/// before reading all of it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct BundleHeader {
+    pub version: u32,
+    pub circuit: CircuitId,
+}
+
+impl BundleHeader {
+    /// Reads the header of a bundle file of any kind, in either format.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        read_file(path.as_ref())
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().map_or(false, |ext| ext == "json")
+}
+
+fn write_file<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    let bytes = if is_json(path) { serde_json::to_vec_pretty(value)? } else { bincode::serialize(value)? };
+    Ok(fs::write(path, bytes)?)
+}
+
+fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+    let bytes = fs::read(path)?;
+    if is_json(path) {
+        Ok(serde_json::from_slice(&bytes)?)
+    } else {
+        Ok(bincode::deserialize(&bytes)?)
+    }
+}
+
+/// Byte strings are hex in human readable formats and raw bytes otherwise.
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            let s = String::deserialize(deserializer)?;
+            hex::decode(s.trim_start_matches("0x")).map_err(D::Error::custom)
+        } else {
+            Vec::<u8>::deserialize(deserializer)
+        }
+    }
+}
+
+/// Field elements as their 32 byte little-endian representation.
+pub(crate) mod hex_fr {
+    use halo2_base::halo2_proofs::halo2curves::{bn256::Fr, ff::PrimeField};
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(x: &Fr, serializer: S) -> Result<S::Ok, S::Error> {
+        super::hex_bytes::serialize(x.to_repr().as_ref(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Fr, D::Error> {
+        let bytes = super::hex_bytes::deserialize(deserializer)?;
+        let repr: [u8; 32] =
+            bytes.try_into().map_err(|_| D::Error::custom("field element must be 32 bytes"))?;
+        Option::from(Fr::from_repr(repr)).ok_or_else(|| D::Error::custom("field element is not reduced"))
+    }
+}
+
+mod hex_fr_vec {
+    use halo2_base::halo2_proofs::halo2curves::bn256::Fr;
+    use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "super::hex_fr")] Fr);
+
+    pub fn serialize<S: Serializer>(xs: &[Fr], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(xs.len()))?;
+        for x in xs {
+            seq.serialize_element(&Wrapper(*x))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Fr>, D::Error> {
+        let xs = Vec::<Wrapper>::deserialize(deserializer)?;
+        Ok(xs.into_iter().map(|Wrapper(x)| x).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle() -> ProofBundle {
+        let image = |width, height, commitment| ImageCommitment {
+            width,
+            height,
+            channels: 3,
+            bit_depth: 8,
+            commitment: Fr::from(commitment),
+        };
+        ProofBundle {
+            version: BUNDLE_VERSION,
+            circuit: CircuitId::new("crop"),
+            vk_hash: Fr::from(42),
+            transcript: Transcript::Poseidon,
+            transformation: Transformation::Crop { x: Some(3), y: None, width: 9, height: 5 },
+            original: image(16, 8, 1),
+            result: image(9, 5, 2),
+            instances: vec![Fr::from(1), Fr::from(2)],
+            proof: vec![0xde, 0xad, 0xbe, 0xef],
+        }
+    }
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let bundle = bundle();
+
+        let json = bundle.to_json().unwrap();
+        assert!(json.contains("\"proof\": \"deadbeef\""));
+        assert!(json.contains("\"crop\": {"));
+        assert!(json.contains("\"transcript\": \"poseidon\""));
+        assert_eq!(ProofBundle::from_json(&json).unwrap(), bundle);
+
+        let bytes = bundle.to_bytes().unwrap();
+        assert!(bytes.len() < json.len());
+        assert_eq!(ProofBundle::from_bytes(&bytes).unwrap(), bundle);
+    }
+
+    #[test]
+    fn test_bundle_header() {
+        let bundle = bundle();
+        let dir = std::env::temp_dir().join(format!("zkimg-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["proof.json", "proof.bin"] {
+            bundle.write(dir.join(name)).unwrap();
+            let header = BundleHeader::read(dir.join(name)).unwrap();
+            assert_eq!(header, BundleHeader { version: BUNDLE_VERSION, circuit: CircuitId::new("crop") });
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_aggregate_roundtrip() {
+        let bundle = bundle();
+        let aggregate = AggregateBundle {
+            version: BUNDLE_VERSION,
+            circuit: CircuitId::new("aggregate"),
+            vk_hash: Fr::from(43),
+            transcript: Transcript::Blake2b,
+            statements: vec![bundle.statement(), bundle.statement()],
+            instances: vec![Fr::from(7); 16],
+            proof: vec![0xca, 0xfe],
+        };
+
+        let json = aggregate.to_json().unwrap();
+        assert!(json.contains("\"statements\": ["));
+        assert_eq!(AggregateBundle::from_json(&json).unwrap(), aggregate);
+        assert_eq!(AggregateBundle::from_bytes(&aggregate.to_bytes().unwrap()).unwrap(), aggregate);
+    }
+
+    #[test]
+    fn test_tiled_roundtrip() {
+        let bundle = bundle();
+        let tiled = TiledBundle {
+            version: BUNDLE_VERSION,
+            circuit: CircuitId::new("tiled_edit"),
+            vk_hash: Fr::from(44),
+            transcript: Transcript::Blake2b,
+            tile_width: 8,
+            tile_height: 4,
+            edits: vec![
+                Transformation::FlattenAlpha { background: vec![255, 255, 255] },
+                Transformation::ConvertDepth { from_bits: 16, to_bits: 8 },
+            ],
+            original: bundle.original,
+            result: bundle.result,
+            instances: vec![Fr::from(5); 14],
+            proof: vec![0xbe, 0xef],
+        };
+
+        // both file encodings, which pick the format from the extension
+        let dir = std::env::temp_dir().join(format!("zkimg-bundle-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["tiled.json", "tiled.bin"] {
+            tiled.write(dir.join(name)).unwrap();
+            assert_eq!(TiledBundle::read(dir.join(name)).unwrap(), tiled);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,8 @@
 #![allow(unused_imports)]
-use std::env::{var, set_var};
-
 use halo2_base::{
     gates::{builder::{
         CircuitBuilderStage, RangeCircuitBuilder, GateThreadBuilder, RangeWithInstanceCircuitBuilder, 
-    }, GateChip, GateInstructions},
+    }, GateChip, GateInstructions, RangeChip, RangeInstructions},
     halo2_proofs::{
         dev::MockProver,
         arithmetic::Field,
@@ -17,13 +15,25 @@ use halo2_base::{
         },
         transcript::{Blake2bWrite, Challenge255, TranscriptWriterBuffer},
     },
-    utils::{fs::gen_srs, ScalarField, BigPrimeField},
+    utils::{ScalarField, BigPrimeField},
     QuantumCell::{Constant, Existing, Witness},
+    poseidon::PoseidonChip,
     Context, AssignedValue,
 };
 
 use halo2_ecc::secp256k1::{FpChip, FqChip};
 
+use itertools::Itertools;
+use rayon::prelude::*;
+
+use crate::config::{CircuitConfig, CircuitParams, ConfiguredCircuit};
+use crate::error::{Error, Result};
+use crate::estimate::gate_params;
+use crate::util::{
+    self, max_value, pack_capacity, packed_row_len, POSEIDON_RATE, POSEIDON_R_F, POSEIDON_R_P,
+    POSEIDON_T,
+};
+
 #[derive(Clone, Copy, Debug)]
 pub struct SchnorrInput {
     pub r: Fp,
@@ -98,74 +108,1077 @@ pub struct SchnorrInput {
 //     res5
 // }
 
-fn crop<F: ScalarField>(
+/// Returns the `crop_width` x `crop_height` window of `original_image` starting
+/// at (`crop_startx`, `crop_starty`). The cropped cells are the original cells
+/// themselves, so the crop costs no gates and nothing about the image contents
+/// ends up in fixed columns. Both images are interleaved with `channels` values
+/// per pixel.
+pub fn crop_image<F: ScalarField>(
+    original_image: &[AssignedValue<F>],
+    width: usize,
+    channels: usize,
+    crop_startx: usize,
+    crop_starty: usize,
+    crop_width: usize,
+    crop_height: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    let height = image_height(original_image.len(), width, channels)?;
+    util::check_crop(width, height, crop_startx, crop_starty, crop_width, crop_height)?;
+    Ok((crop_starty..crop_starty + crop_height)
+        .flat_map(|old_y| {
+            let row_start = (old_y * width + crop_startx) * channels;
+            original_image[row_start..row_start + crop_width * channels].iter().copied()
+        })
+        .collect())
+}
+
+/// Number of rows of `len` values laid out `width * channels` to a row.
+fn image_height(len: usize, width: usize, channels: usize) -> Result<usize> {
+    if width == 0 || channels == 0 || len % (width * channels) != 0 {
+        return Err(Error::InvalidDimensions(format!(
+            "{len} values is not a whole number of {width}x{channels} rows"
+        )));
+    }
+    Ok(len / (width * channels))
+}
+
+/// Range checks every value of `image` to `bit_depth` bits. Pixels that are only
+/// copied (as in [`crop_image`]) never need this, but any gadget doing arithmetic
+/// on them does.
+pub fn range_check_pixels<F: ScalarField>(
     ctx: &mut Context<F>,
-    x: F,
-    make_public: &mut Vec<AssignedValue<F>>,
+    range: &RangeChip<F>,
+    image: &[AssignedValue<F>],
+    bit_depth: usize,
 ) {
-    let gate = GateChip::<F>::default();
-    let x = ctx.load_witness(x);    
-    let x_sq = gate.mul(ctx, x, x);
-    let c = F::from(72);
-    let out = gate.add(ctx, x_sq, Constant(c));
-    // gate.assert_is_const(ctx, &out, &F::from(1));
-    println!("x: {:?}", x.value());
-    println!("val_assigned: {:?}", out.value());
-    assert_eq!(*x.value() * x.value() + c, *out.value());
-}
-
-pub fn run() {
-    let k = 8;
-    let stage = CircuitBuilderStage::Mock;
-    let params = gen_srs(k);
-
-    let mut builder: GateThreadBuilder<Fr> = match stage {
-        CircuitBuilderStage::Prover => GateThreadBuilder::new(true),
-        _ => GateThreadBuilder::new(false),
-    };
+    for value in image {
+        range.range_check(ctx, *value, bit_depth);
+    }
+}
+
+/// Composites an image whose last channel is alpha onto a solid `background`
+/// colour and returns the flattened pixels (one fewer channel), rounded the same
+/// way as [`crate::util::flatten_alpha`]. The pixels are range checked to
+/// `bit_depth` bits; the background is part of the circuit rather than a
+/// witness, so one that does not fit is rejected before any constraint.
+pub fn flatten_alpha<F: ScalarField>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    image: &[AssignedValue<F>],
+    channels: usize,
+    bit_depth: usize,
+    background: &[u64],
+) -> Result<Vec<AssignedValue<F>>> {
+    util::check_bit_depth(bit_depth)?;
+    if channels < 2 || background.len() + 1 != channels || image.len() % channels != 0 {
+        return Err(Error::InvalidDimensions(format!(
+            "{channels} channels with alpha need {} background values and whole pixels",
+            channels.saturating_sub(1)
+        )));
+    }
+    let max = max_value(bit_depth);
+    if let Some((index, &value)) = background.iter().enumerate().find(|(_, &v)| v > max) {
+        return Err(Error::PixelOutOfRange { index, value, bit_depth });
+    }
+    let gate = range.gate();
+    let colour_channels = channels - 1;
+    range_check_pixels(ctx, range, image, bit_depth);
 
-    let lookup_bits: usize = var("LOOKUP_BITS")
-    .map(|str| {
-        let lookup_bits = str.parse().unwrap();
-        lookup_bits
-    })
-    .unwrap_or(0);
-    set_var("LOOKUP_BITS", lookup_bits.to_string());
-    let mut assigned_instances = vec![];
-    crop(builder.main(0), Fr::from(7), &mut assigned_instances);
-
-    let minimum_rows = var("MINIMUM_ROWS").unwrap_or_else(|_| "9".to_string()).parse().unwrap();
-    match stage {
-        CircuitBuilderStage::Prover => {}
-        _ => {
-            builder.config(k as usize, Some(minimum_rows));
+    let mut flattened = Vec::with_capacity(image.len() / channels * colour_channels);
+    for pixel in image.chunks(channels) {
+        let alpha = pixel[colour_channels];
+        let inv_alpha = gate.sub(ctx, Constant(F::from(max)), alpha);
+        for (c, bg) in pixel[..colour_channels].iter().zip(background) {
+            // c * a + bg * (max - a) + max / 2 < 2^(2 * bit_depth)
+            let fg = gate.mul(ctx, *c, alpha);
+            let blended = gate.mul_add(ctx, inv_alpha, Constant(F::from(*bg)), fg);
+            let blended = gate.add(ctx, blended, Constant(F::from(max / 2)));
+            let (out, _) = range.div_mod(ctx, blended, max, 2 * bit_depth);
+            flattened.push(out);
         }
-    };
+    }
+    Ok(flattened)
+}
+
+/// Rescales `from_bits`-bit pixels to `to_bits` bits with rounding, matching
+/// [`crate::util::convert_depth`]. Used to prove an 8-bit export of a
+/// high-bit-depth master.
+pub fn convert_depth<F: ScalarField>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    image: &[AssignedValue<F>],
+    from_bits: usize,
+    to_bits: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    util::check_bit_depth(from_bits)?;
+    util::check_bit_depth(to_bits)?;
+    let gate = range.gate();
+    let max_from = max_value(from_bits);
+    let max_to = max_value(to_bits);
+    range_check_pixels(ctx, range, image, from_bits);
+
+    Ok(image
+        .iter()
+        .map(|v| {
+            let scaled = gate.mul_add(ctx, *v, Constant(F::from(max_to)), Constant(F::from(max_from / 2)));
+            let (out, _) = range.div_mod(ctx, scaled, max_from, from_bits + to_bits);
+            out
+        })
+        .collect())
+}
+
+/// Packs up to [`pack_capacity`] `bit_depth`-bit values into one field element,
+/// little-endian, matching [`crate::util::pack_pixels`]. The values must already
+/// be range checked for the packing to be injective.
+pub fn pack_pixels<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    values: &[AssignedValue<F>],
+    bit_depth: usize,
+) -> Vec<AssignedValue<F>> {
+    values
+        .chunks(pack_capacity(bit_depth))
+        .map(|chunk| {
+            let shifts = (0..chunk.len()).map(|i| Constant(gate.pow_of_two()[i * bit_depth]));
+            gate.inner_product(ctx, chunk.iter().copied(), shifts)
+        })
+        .collect()
+}
+
+/// Unpacks `count` range checked `bit_depth`-bit values from a packed word. Only
+/// needed where a gadget does arithmetic on individual pixels; copies and
+/// equality checks work directly on packed words.
+pub fn unpack_pixels<F: ScalarField>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    packed: AssignedValue<F>,
+    count: usize,
+    bit_depth: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    util::check_bit_depth(bit_depth)?;
+    if count > pack_capacity(bit_depth) {
+        return Err(Error::InvalidDimensions(format!(
+            "a packed word holds at most {} {bit_depth}-bit values, not {count}",
+            pack_capacity(bit_depth)
+        )));
+    }
+    let values = ctx.assign_witnesses(
+        packed.value().to_u64_limbs(count, bit_depth).into_iter().map(F::from),
+    );
+    range_check_pixels(ctx, range, &values, bit_depth);
+    let repacked = pack_pixels(ctx, range.gate(), &values, bit_depth)[0];
+    ctx.constrain_equal(&repacked, &packed);
+    Ok(values)
+}
+
+/// Splits a packed word holding `len` values into pieces at the value offsets in
+/// `cuts` (strictly increasing, inside `0..len`). Each piece is itself a packed
+/// word of the values between two cuts, so only `len * bit_depth` bits are range
+/// checked in total rather than every value separately.
+fn split_packed<F: ScalarField>(
+    ctx: &mut Context<F>,
+    range: &RangeChip<F>,
+    packed: AssignedValue<F>,
+    len: usize,
+    cuts: &[usize],
+    bit_depth: usize,
+) -> Vec<AssignedValue<F>> {
+    if cuts.is_empty() {
+        return vec![packed];
+    }
+    let gate = range.gate();
+    let bounds = [0].into_iter().chain(cuts.iter().copied()).chain([len]).collect_vec();
+    let values = packed.value().to_u64_limbs(len, bit_depth);
+
+    let pieces = bounds
+        .iter()
+        .tuple_windows()
+        .map(|(&start, &end)| {
+            let piece = ctx.load_witness(util::pack_pixels(&values[start..end], bit_depth)[0]);
+            range.range_check(ctx, piece, (end - start) * bit_depth);
+            piece
+        })
+        .collect_vec();
+    let shifts = bounds.iter().take(pieces.len()).map(|&start| Constant(gate.pow_of_two()[start * bit_depth]));
+    let recomposed = gate.inner_product(ctx, pieces.iter().copied(), shifts);
+    ctx.constrain_equal(&recomposed, &packed);
+    pieces
+}
+
+/// Crops an image packed row by row with [`crate::util::pack_image`] and returns
+/// the cropped image packed the same way. Original words that fall entirely
+/// inside the crop are reused as is; words straddling the crop edges or a
+/// cropped word boundary are split once, so the cost scales with the number of
+/// packed words in the crop window rather than the number of pixels.
+pub fn crop_packed<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    original: &[AssignedValue<F>],
+    width: usize,
+    channels: usize,
+    bit_depth: usize,
+    crop_startx: usize,
+    crop_starty: usize,
+    crop_width: usize,
+    crop_height: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    util::check_bit_depth(bit_depth)?;
+    let row_words = packed_row_len(width, channels, bit_depth);
+    let height = image_height(original.len(), row_words, 1)?;
+    util::check_crop(width, height, crop_startx, crop_starty, crop_width, crop_height)?;
+    let gate = range.gate();
+    let capacity = pack_capacity(bit_depth);
+    let row_len = width * channels;
+    // value offsets of the crop window within a row
+    let start = crop_startx * channels;
+    let end = start + crop_width * channels;
+
+    let rows = original[crop_starty * row_words..][..crop_height * row_words].chunks(row_words).collect_vec();
+    let cropped = parallelize(builder, rows, |ctx, row| {
+        // (offset of the piece within the row, piece)
+        let mut pieces = vec![];
+        for w in start / capacity..=(end - 1) / capacity {
+            let word_start = w * capacity;
+            let word_len = capacity.min(row_len - word_start);
+            let cuts = (word_start + 1..word_start + word_len)
+                .filter(|&i| i == start || i == end || (i > start && i < end && (i - start) % capacity == 0))
+                .collect_vec();
+            let bounds = [word_start].into_iter().chain(cuts.iter().copied());
+            let relative_cuts = cuts.iter().map(|i| i - word_start).collect_vec();
+            for (offset, piece) in bounds.zip(split_packed(ctx, range, row[w], word_len, &relative_cuts, bit_depth)) {
+                if offset >= start && offset < end {
+                    pieces.push((offset, piece));
+                }
+            }
+        }
+
+        // reassemble the pieces into words aligned to the cropped row
+        let mut cropped = Vec::with_capacity(packed_row_len(crop_width, channels, bit_depth));
+        for (_, group) in &pieces.into_iter().group_by(|(offset, _)| (offset - start) / capacity) {
+            let mut group = group.into_iter();
+            let (word_start, mut word) = group.next().unwrap();
+            for (offset, piece) in group {
+                let shift = Constant(gate.pow_of_two()[(offset - word_start) * bit_depth]);
+                word = gate.mul_add(ctx, piece, shift, word);
+            }
+            cropped.push(word);
+        }
+        cropped
+    });
+    Ok(cropped.concat())
+}
+
+pub fn poseidon_hash<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    inputs: &[AssignedValue<F>],
+) -> AssignedValue<F> {
+    let mut hasher =
+        PoseidonChip::<F, POSEIDON_T, POSEIDON_RATE>::new(ctx, POSEIDON_R_F, POSEIDON_R_P).unwrap();
+    hasher.update(inputs);
+    hasher.squeeze(ctx, gate).unwrap()
+}
+
+/// In-circuit version of [`crate::util::commit_image`] over an image already
+/// packed row by row.
+pub fn commit_image<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    packed: &[AssignedValue<F>],
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+) -> AssignedValue<F> {
+    let header = [width, height, channels, bit_depth].map(|x| ctx.load_constant(F::from(x as u64)));
+    poseidon_hash(ctx, gate, &[&header[..], packed].concat())
+}
+
+/// In-circuit version of [`crate::util::hiding_commitment`], with `salt` a
+/// private witness.
+pub fn hiding_commitment<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    commitment: AssignedValue<F>,
+    salt: AssignedValue<F>,
+) -> AssignedValue<F> {
+    poseidon_hash(ctx, gate, &[commitment, salt])
+}
+
+/// In-circuit version of [`crate::util::merkle_root`].
+pub fn merkle_root<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    leaves: &[AssignedValue<F>],
+) -> AssignedValue<F> {
+    let mut layer = leaves.to_vec();
+    let zero = ctx.load_constant(F::zero());
+    layer.resize(leaves.len().next_power_of_two(), zero);
+    while layer.len() > 1 {
+        layer = layer.chunks(2).map(|pair| poseidon_hash(ctx, gate, pair)).collect();
+    }
+    layer[0]
+}
+
+/// In-circuit version of [`crate::util::merkle_root_from_path`], with the
+/// leaf's index given by its little-endian bits, one per level of `path`.
+pub fn merkle_root_from_path<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    leaf: AssignedValue<F>,
+    index_bits: &[AssignedValue<F>],
+    path: &[AssignedValue<F>],
+) -> AssignedValue<F> {
+    let mut node = leaf;
+    for (bit, sibling) in index_bits.iter().zip_eq(path) {
+        let left = gate.select(ctx, *sibling, node, *bit);
+        let right = gate.select(ctx, node, *sibling, *bit);
+        node = poseidon_hash(ctx, gate, &[left, right]);
+    }
+    node
+}
+
+/// In-circuit version of [`crate::util::commit_merkle_root`].
+pub fn commit_merkle_root<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    root: AssignedValue<F>,
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    tile_width: usize,
+    tile_height: usize,
+) -> AssignedValue<F> {
+    let header = [width, height, channels, bit_depth, tile_width, tile_height]
+        .map(|x| ctx.load_constant(F::from(x as u64)));
+    poseidon_hash(ctx, gate, &[&header[..], &[root]].concat())
+}
+
+/// In-circuit version of [`crate::util::tiled_commitment`] over the
+/// commitments of the tiles, in row-major order.
+pub fn tiled_commitment<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    leaves: &[AssignedValue<F>],
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    tile_width: usize,
+    tile_height: usize,
+) -> AssignedValue<F> {
+    let root = merkle_root(ctx, gate, leaves);
+    commit_merkle_root(ctx, gate, root, width, height, channels, bit_depth, tile_width, tile_height)
+}
+
+/// Runs `f` on each input in a new thread context of `builder`, in parallel
+/// with rayon, and returns the outputs in input order. The thread ids are taken
+/// in input order before any work starts and the contexts are appended in the
+/// same order, so the layout, and with it the break points stored at keygen,
+/// does not depend on how rayon schedules the work.
+pub fn parallelize<F, T, R>(
+    builder: &mut GateThreadBuilder<F>,
+    inputs: Vec<T>,
+    f: impl Fn(&mut Context<F>, T) -> R + Send + Sync,
+) -> Vec<R>
+where
+    F: ScalarField,
+    T: Send,
+    R: Send,
+{
+    let witness_gen_only = builder.witness_gen_only();
+    let ids = inputs.iter().map(|_| builder.get_new_thread_id()).collect_vec();
+    let (outputs, mut threads): (Vec<_>, Vec<_>) = inputs
+        .into_par_iter()
+        .zip(ids)
+        .map(|(input, id)| {
+            let mut ctx = Context::new(witness_gen_only, id);
+            let output = f(&mut ctx, input);
+            (output, ctx)
+        })
+        .unzip();
+    builder.threads[0].append(&mut threads);
+    outputs
+}
+
+/// Assigns `image` packed row by row as private witnesses, one thread per row.
+/// Every value must fit in `bit_depth` bits for a commitment over the words to
+/// be binding; range checking whole words keeps this at one check per packed
+/// word, and values that would fail it are rejected before any witness is
+/// assigned.
+pub fn assign_packed_image<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    image: &util::Image,
+    width: usize,
+    channels: usize,
+    bit_depth: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    let height = image_height(image.len(), width, channels)?;
+    util::check_image(image, width, height, channels, bit_depth)?;
+    let words = util::pack_image::<F>(image, width, channels, bit_depth);
+    let rows = words.chunks(packed_row_len(width, channels, bit_depth)).collect_vec();
+    let packed = parallelize(builder, rows, |ctx, row| {
+        let row = ctx.assign_witnesses(row.iter().copied());
+        for (i, word) in row.iter().enumerate() {
+            range.range_check(ctx, *word, packed_word_len(width, channels, bit_depth, i) * bit_depth);
+        }
+        row
+    });
+    Ok(packed.concat())
+}
+
+/// Number of values held by the `i`-th packed word of a row.
+fn packed_word_len(width: usize, channels: usize, bit_depth: usize, i: usize) -> usize {
+    let capacity = pack_capacity(bit_depth);
+    capacity.min(width * channels - i * capacity)
+}
+
+/// Crops `original` and exposes the commitments to the original and the
+/// cropped image, in that order. Both images are private witnesses; only the
+/// dimensions and crop window are part of the circuit, so a single proving and
+/// verifying key covers every image of the given size.
+pub fn crop_and_commit<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    original: &util::Image,
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    crop_startx: usize,
+    crop_starty: usize,
+    crop_width: usize,
+    crop_height: usize,
+    make_public: &mut Vec<AssignedValue<F>>,
+) -> Result<()> {
+    util::check_image(original, width, height, channels, bit_depth)?;
+    util::check_crop(width, height, crop_startx, crop_starty, crop_width, crop_height)?;
+    let gate = range.gate();
+
+    let packed = assign_packed_image(builder, range, original, width, channels, bit_depth)?;
+    let original_commitment = commit_image(builder.main(0), gate, &packed, width, height, channels, bit_depth);
+
+    let cropped = crop_packed(
+        builder,
+        range,
+        &packed,
+        width,
+        channels,
+        bit_depth,
+        crop_startx,
+        crop_starty,
+        crop_width,
+        crop_height,
+    )?;
+    let cropped_commitment =
+        commit_image(builder.main(0), gate, &cropped, crop_width, crop_height, channels, bit_depth);
+
+    make_public.extend([original_commitment, cropped_commitment]);
+    Ok(())
+}
 
+/// Shifts `values` left by `shift * stride` elements, where `shift_bits` are
+/// the little-endian bits of `shift`, and returns the first `out_len` elements.
+/// Each bit is one layer of selects (a barrel shifter), so the cost is
+/// logarithmic in the largest possible shift. Callers must constrain `shift` so
+/// that `shift * stride + out_len <= values.len()`.
+fn shift_left<F: ScalarField>(
+    ctx: &mut Context<F>,
+    gate: &impl GateInstructions<F>,
+    mut values: Vec<AssignedValue<F>>,
+    shift_bits: &[AssignedValue<F>],
+    stride: usize,
+    out_len: usize,
+) -> Vec<AssignedValue<F>> {
+    for (b, bit) in shift_bits.iter().enumerate().rev() {
+        let step = (1 << b) * stride;
+        // the lower bits can shift by at most (2^b - 1) * stride more
+        let len = values.len().min(out_len + step - stride);
+        values = (0..len)
+            .map(|i| match values.get(i + step) {
+                Some(shifted) => gate.select(ctx, *shifted, values[i], *bit),
+                // only reachable by a shift the caller's range check excludes
+                None => values[i],
+            })
+            .collect();
+    }
+    values.truncate(out_len);
+    values
+}
+
+/// Same statement as [`crop_and_commit`], but the crop offset is a witness
+/// rather than part of the circuit, so one key covers every crop position of a
+/// given size. The rows are selected on packed words and the columns on
+/// unpacked values, both with barrel shifters. The offset is appended to the
+/// public instances after the two commitments only if `reveal_offset` is set.
+pub fn dynamic_crop_and_commit<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    original: &util::Image,
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    crop_startx: usize,
+    crop_starty: usize,
+    crop_width: usize,
+    crop_height: usize,
+    reveal_offset: bool,
+    make_public: &mut Vec<AssignedValue<F>>,
+) -> Result<()> {
+    util::check_image(original, width, height, channels, bit_depth)?;
+    util::check_crop(width, height, crop_startx, crop_starty, crop_width, crop_height)?;
+    let gate = range.gate();
+    let max_x = width - crop_width;
+    let max_y = height - crop_height;
+
+    let ctx = builder.main(0);
+    let [x, y] = [crop_startx, crop_starty].map(|v| ctx.load_witness(F::from(v as u64)));
+    range.check_less_than_safe(ctx, x, max_x as u64 + 1);
+    range.check_less_than_safe(ctx, y, max_y as u64 + 1);
+    let x_bits = gate.num_to_bits(ctx, x, offset_bits(max_x));
+    let y_bits = gate.num_to_bits(ctx, y, offset_bits(max_y));
+
+    let packed = assign_packed_image(builder, range, original, width, channels, bit_depth)?;
+    let original_commitment = commit_image(builder.main(0), gate, &packed, width, height, channels, bit_depth);
+
+    let cropped =
+        shift_crop(builder, range, packed, width, channels, bit_depth, &x_bits, &y_bits, crop_width, crop_height)?;
+    let cropped_commitment =
+        commit_image(builder.main(0), gate, &cropped, crop_width, crop_height, channels, bit_depth);
+
+    make_public.extend([original_commitment, cropped_commitment]);
+    if reveal_offset {
+        make_public.extend([x, y]);
+    }
+    Ok(())
+}
+
+/// Same statement as [`dynamic_crop_and_commit`], but the original is
+/// committed as a Merkle tree of full-width tiles of `rows_per_leaf` rows, see
+/// [`crate::util::commit_tiled_image`]. Only the leaves the crop touches are
+/// hashed from their pixels, and each is checked against the root with its
+/// path; the rest of the original is never assigned. How many leaves are
+/// opened depends only on the crop height, and the first one is a witness next
+/// to the offset, so one key still covers every crop position.
+pub fn merkle_crop_and_commit<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    original: &util::Image,
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    rows_per_leaf: usize,
+    crop_startx: usize,
+    crop_starty: usize,
+    crop_width: usize,
+    crop_height: usize,
+    reveal_offset: bool,
+    make_public: &mut Vec<AssignedValue<F>>,
+) -> Result<()> {
+    util::check_image(original, width, height, channels, bit_depth)?;
+    util::check_crop(width, height, crop_startx, crop_starty, crop_width, crop_height)?;
+    let (_, num_leaves) = util::tile_grid(width, height, width, rows_per_leaf)?;
+    let gate = range.gate();
+    // crop_height rows starting anywhere in a leaf touch at most this many leaves
+    let opened = num_leaves.min((crop_height + rows_per_leaf - 2) / rows_per_leaf + 1);
+    let first = (crop_starty / rows_per_leaf).min(num_leaves - opened);
+    let max_x = width - crop_width;
+    let max_y = opened * rows_per_leaf - crop_height;
+
+    // y is relative to the first opened row
+    let ctx = builder.main(0);
+    let [x, first_leaf, y] = [crop_startx, first, crop_starty - first * rows_per_leaf]
+        .map(|v| ctx.load_witness(F::from(v as u64)));
+    range.check_less_than_safe(ctx, x, max_x as u64 + 1);
+    range.check_less_than_safe(ctx, first_leaf, (num_leaves - opened) as u64 + 1);
+    range.check_less_than_safe(ctx, y, max_y as u64 + 1);
+    let x_bits = gate.num_to_bits(ctx, x, offset_bits(max_x));
+    let y_bits = gate.num_to_bits(ctx, y, offset_bits(max_y));
+
+    let leaves = util::split_tiles(original, width, height, channels, width, rows_per_leaf)?
+        .iter()
+        .map(|leaf| util::commit_image(leaf, width, rows_per_leaf, channels, bit_depth))
+        .collect::<Result<Vec<F>>>()?;
+    let tree = util::merkle_tree(&leaves);
+    let depth = tree.len() - 1;
+
+    let leaf_len = width * rows_per_leaf * channels;
+    let rows = original[first * leaf_len..(first + opened) * leaf_len].to_vec();
+    let packed = assign_packed_image(builder, range, &rows, width, channels, bit_depth)?;
+    let leaves = packed.chunks(packed.len() / opened).enumerate().collect_vec();
+    let roots = parallelize(builder, leaves, |ctx, (i, words)| {
+        let leaf = commit_image(ctx, gate, words, width, rows_per_leaf, channels, bit_depth);
+        let path = ctx.assign_witnesses(util::merkle_path(&tree, first + i));
+        let index = gate.add(ctx, first_leaf, Constant(F::from(i as u64)));
+        // a single leaf is the root, and its index can only be zero
+        let index_bits = if depth == 0 { vec![] } else { gate.num_to_bits(ctx, index, depth) };
+        merkle_root_from_path(ctx, gate, leaf, &index_bits, &path)
+    });
+    let ctx = builder.main(0);
+    for root in &roots[1..] {
+        ctx.constrain_equal(&roots[0], root);
+    }
+    let original_commitment = commit_merkle_root(
+        ctx,
+        gate,
+        roots[0],
+        width,
+        height,
+        channels,
+        bit_depth,
+        width,
+        rows_per_leaf,
+    );
+
+    let cropped =
+        shift_crop(builder, range, packed, width, channels, bit_depth, &x_bits, &y_bits, crop_width, crop_height)?;
+    let ctx = builder.main(0);
+    let cropped_commitment =
+        commit_image(ctx, gate, &cropped, crop_width, crop_height, channels, bit_depth);
+
+    make_public.extend([original_commitment, cropped_commitment]);
+    if reveal_offset {
+        let y = gate.mul_add(ctx, first_leaf, Constant(F::from(rows_per_leaf as u64)), y);
+        make_public.extend([x, y]);
+    }
+    Ok(())
+}
+
+/// Bits of an offset of at most `max`: at least one, so an offset that can
+/// only be zero still gets decomposed.
+fn offset_bits(max: usize) -> usize {
+    ((usize::BITS - max.leading_zeros()) as usize).max(1)
+}
+
+/// The `crop_width` by `crop_height` window of an image packed row by row, at
+/// the offset whose bits are `x_bits` and `y_bits`. The rows are selected on
+/// packed words and the columns on unpacked values, both with [`shift_left`],
+/// whose range condition on the offset the caller checks. The rows are shifted
+/// in the main thread and each cropped row is then cut in its own.
+fn shift_crop<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    packed: Vec<AssignedValue<F>>,
+    width: usize,
+    channels: usize,
+    bit_depth: usize,
+    x_bits: &[AssignedValue<F>],
+    y_bits: &[AssignedValue<F>],
+    crop_width: usize,
+    crop_height: usize,
+) -> Result<Vec<AssignedValue<F>>> {
+    let gate = range.gate();
+    let row_words = packed_row_len(width, channels, bit_depth);
+    let rows = shift_left(builder.main(0), gate, packed, y_bits, row_words, crop_height * row_words);
+
+    let cropped = parallelize(builder, rows.chunks(row_words).collect_vec(), |ctx, row| {
+        let values = row
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                unpack_pixels(ctx, range, *word, packed_word_len(width, channels, bit_depth, i), bit_depth)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .concat();
+        let values = shift_left(ctx, gate, values, x_bits, channels, crop_width * channels);
+        Ok(pack_pixels(ctx, gate, &values, bit_depth))
+    });
+    Ok(cropped.into_iter().collect::<Result<Vec<_>>>()?.concat())
+}
+
+/// Applies `edits` in order to `original` and exposes the commitments to the
+/// original and the result, like [`crop_and_commit`]. Crops commute with the
+/// per-pixel edits, so all crops are composed into one [`crop_packed`] window
+/// first and only the cropped words are unpacked, and only if a per-pixel edit
+/// follows, each row in its own thread. Crop offsets are part of the circuit.
+pub fn edit_and_commit<F: ScalarField>(
+    builder: &mut GateThreadBuilder<F>,
+    range: &RangeChip<F>,
+    original: &util::Image,
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    edits: &[util::Edit],
+    make_public: &mut Vec<AssignedValue<F>>,
+) -> Result<()> {
+    util::check_image(original, width, height, channels, bit_depth)?;
+    let gate = range.gate();
+
+    let packed = assign_packed_image(builder, range, original, width, channels, bit_depth)?;
+    let original_commitment = commit_image(builder.main(0), gate, &packed, width, height, channels, bit_depth);
+
+    let (x, y, crop_width, crop_height) = util::crop_window(width, height, edits)?;
+    let mut words =
+        crop_packed(builder, range, &packed, width, channels, bit_depth, x, y, crop_width, crop_height)?;
+    let (mut out_channels, mut out_depth) = (channels, bit_depth);
+
+    if edits.iter().any(|edit| !matches!(edit, util::Edit::Crop { .. })) {
+        let row_words = packed_row_len(crop_width, channels, bit_depth);
+        let rows = parallelize(builder, words.chunks(row_words).collect_vec(), |ctx, row| {
+            let mut values = Vec::with_capacity(crop_width * channels);
+            for (i, word) in row.iter().enumerate() {
+                let count = packed_word_len(crop_width, channels, bit_depth, i);
+                values.extend(unpack_pixels(ctx, range, *word, count, bit_depth)?);
+            }
+            let (mut out_channels, mut out_depth) = (channels, bit_depth);
+            for edit in edits {
+                match edit {
+                    util::Edit::Crop { .. } => {}
+                    util::Edit::FlattenAlpha { background } => {
+                        values = flatten_alpha(ctx, range, &values, out_channels, out_depth, background)?;
+                        out_channels -= 1;
+                    }
+                    util::Edit::ConvertDepth { to_bits } => {
+                        values = convert_depth(ctx, range, &values, out_depth, *to_bits)?;
+                        out_depth = *to_bits;
+                    }
+                }
+            }
+            Ok((pack_pixels(ctx, gate, &values, out_depth), out_channels, out_depth))
+        });
+        words = vec![];
+        for row in rows {
+            let (row, row_channels, row_depth) = row?;
+            words.extend(row);
+            (out_channels, out_depth) = (row_channels, row_depth);
+        }
+    }
+    let result_commitment =
+        commit_image(builder.main(0), gate, &words, crop_width, crop_height, out_channels, out_depth);
+
+    make_public.extend([original_commitment, result_commitment]);
+    Ok(())
+}
+
+/// Turns a `builder` holding all constraints into a circuit for `stage`.
+///
+/// At keygen (and mock) the gate parameters are recorded in `config`; the
+/// caller stores `circuit.break_points()` there after `keygen_pk`. At proving
+/// the witness generation is checked against `config` so that a changed circuit
+/// shape or a config that never went through keygen is an error rather than a
+/// panic inside halo2-lib.
+pub fn build_circuit<F: ScalarField>(
+    stage: CircuitBuilderStage,
+    builder: GateThreadBuilder<F>,
+    assigned_instances: Vec<AssignedValue<F>>,
+    config: &mut CircuitConfig,
+) -> Result<ConfiguredCircuit<F>> {
+    config.params.check()?;
+    let gate_params = gate_params(&builder, config.params.k, config.params.minimum_rows);
     let circuit = match stage {
-        CircuitBuilderStage::Prover => RangeCircuitBuilder::prover(
-            builder,
-            None.unwrap()
-        ),
-        CircuitBuilderStage::Keygen => RangeCircuitBuilder::keygen(builder),
         CircuitBuilderStage::Mock => {
-            println!("called");
-            RangeCircuitBuilder::mock(builder)
+            config.gate_params = gate_params;
+            config.apply();
+            RangeWithInstanceCircuitBuilder::mock(builder, assigned_instances)
+        }
+        CircuitBuilderStage::Keygen => {
+            config.gate_params = gate_params;
+            config.apply();
+            RangeWithInstanceCircuitBuilder::keygen(builder, assigned_instances)
+        }
+        CircuitBuilderStage::Prover => {
+            config.check_shape(&gate_params)?;
+            config.apply();
+            RangeWithInstanceCircuitBuilder::prover(builder, assigned_instances, config.break_points.clone())
         }
     };
+    Ok(ConfiguredCircuit(circuit))
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let public_io: Vec<Fr> = assigned_instances.iter().map(|v| *v.value()).collect();
+    /// Builds the mock circuit with the config keygen would record for it.
+    fn mock_circuit(
+        builder: GateThreadBuilder<Fr>,
+        assigned_instances: Vec<AssignedValue<Fr>>,
+        k: usize,
+        lookup_bits: usize,
+    ) -> ConfiguredCircuit<Fr> {
+        let mut config = CircuitConfig::new(CircuitParams::new(k, lookup_bits, Some(9)));
+        build_circuit(CircuitBuilderStage::Mock, builder, assigned_instances, &mut config).unwrap()
+    }
+
+    #[test]
+    fn test_flatten_alpha() {
+        let k = 10;
+        let lookup_bits = 8;
+        for (bit_depth, background) in [(8, [255, 0, 64]), (16, [65535, 0, 4096])] {
+            let image = util::generate_image(4, 4, 4, bit_depth).unwrap();
+            let expected = util::flatten_alpha(&image, 4, bit_depth, &background).unwrap();
+
+            let mut builder = GateThreadBuilder::<Fr>::mock();
+            let range = RangeChip::default(lookup_bits);
+            let ctx = builder.main(0);
+            let image = ctx.assign_witnesses(image.iter().map(|&x| Fr::from(x)));
+            let flattened = flatten_alpha(ctx, &range, &image, 4, bit_depth, &background).unwrap();
+            for (out, expected) in flattened.iter().zip(expected) {
+                assert_eq!(*out.value(), Fr::from(expected));
+            }
+
+            let circuit = mock_circuit(builder, vec![], k, lookup_bits);
+            MockProver::run(k as u32, &circuit, vec![vec![]]).unwrap().assert_satisfied();
+        }
+
+        // a background that does not fit the bit depth
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let range = RangeChip::default(lookup_bits);
+        let ctx = builder.main(0);
+        let image = ctx.assign_witnesses(util::generate_image(4, 4, 4, 8).unwrap().iter().map(|&x| Fr::from(x)));
+        assert!(matches!(
+            flatten_alpha(ctx, &range, &image, 4, 8, &[255, 256, 0]),
+            Err(Error::PixelOutOfRange { index: 1, value: 256, bit_depth: 8 })
+        ));
+    }
+
+    #[test]
+    fn test_convert_depth() {
+        let k = 10;
+        let lookup_bits = 8;
+        let image = util::generate_image(4, 4, 3, 16).unwrap();
+        let expected = util::convert_depth(&image, 16, 8).unwrap();
+
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let range = RangeChip::default(lookup_bits);
+        let ctx = builder.main(0);
+        let image = ctx.assign_witnesses(image.iter().map(|&x| Fr::from(x)));
+        let converted = convert_depth(ctx, &range, &image, 16, 8).unwrap();
+        for (out, expected) in converted.iter().zip(expected) {
+            assert_eq!(*out.value(), Fr::from(expected));
+        }
 
-    println!("here3");
+        let circuit = mock_circuit(builder, vec![], k, lookup_bits);
+        MockProver::run(k as u32, &circuit, vec![vec![]]).unwrap().assert_satisfied();
+    }
+
+    #[test]
+    fn test_parallelize() {
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let gate = GateChip::default();
+        builder.main(0).load_zero();
+        let outputs = parallelize(&mut builder, (0..16u64).collect(), |ctx, i| {
+            // uneven work, so the threads finish out of order
+            (0..i % 5).fold(ctx.load_witness(Fr::from(i)), |v, _| gate.mul(ctx, v, v))
+        });
+        for (i, output) in outputs.iter().enumerate() {
+            assert_eq!(*output.value(), Fr::from(i as u64).pow_vartime([1 << (i % 5)]));
+        }
+        let ids = builder.threads[0].iter().map(|ctx| ctx.context_id).collect_vec();
+        assert_eq!(ids, (0..17).collect_vec());
+    }
+
+    #[test]
+    fn test_crop_packed() {
+        let k = 12;
+        let lookup_bits = 11;
+        let (width, height, channels, bit_depth) = (40, 6, 3, 8);
+        // (x, y, w, h): aligned, unaligned, and a crop ending mid-word
+        for (x, y, w, h) in [(0, 0, 40, 6), (7, 1, 25, 4), (3, 2, 5, 1)] {
+            let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+            let cropped = util::crop_image(&image, width, channels, x, y, w, h).unwrap();
+            let expected = util::pack_image::<Fr>(&cropped, w, channels, bit_depth);
 
-    let c = RangeWithInstanceCircuitBuilder::new(circuit, assigned_instances);
+            let mut builder = GateThreadBuilder::<Fr>::mock();
+            let range = RangeChip::default(lookup_bits);
+            let original = builder.main(0).assign_witnesses(util::pack_image(&image, width, channels, bit_depth));
+            let result = crop_packed(&mut builder, &range, &original, width, channels, bit_depth, x, y, w, h).unwrap();
+            assert_eq!(result.iter().map(|v| *v.value()).collect::<Vec<_>>(), expected);
 
-    println!("here4");
+            let circuit = mock_circuit(builder, vec![], k, lookup_bits);
+            MockProver::run(k as u32, &circuit, vec![vec![]]).unwrap().assert_satisfied();
+        }
+    }
+
+    #[test]
+    fn test_crop_and_commit() {
+        let k = 13;
+        let lookup_bits = 12;
+        let (width, height, channels, bit_depth) = (16, 8, 3, 8);
+        let (x, y, w, h) = (3, 2, 9, 5);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let cropped = util::crop_image(&image, width, channels, x, y, w, h).unwrap();
+        let public_io = vec![
+            util::commit_image::<Fr>(&image, width, height, channels, bit_depth).unwrap(),
+            util::commit_image::<Fr>(&cropped, w, h, channels, bit_depth).unwrap(),
+        ];
 
-    match MockProver::run(k, &c, vec![public_io.clone()]) {
-        Ok(result) => result.assert_satisfied(),
-        Err(msg) => println!("failure: {}", msg),
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let range = RangeChip::default(lookup_bits);
+        let mut assigned_instances = vec![];
+        crop_and_commit(
+            &mut builder,
+            &range,
+            &image,
+            width,
+            height,
+            channels,
+            bit_depth,
+            x,
+            y,
+            w,
+            h,
+            &mut assigned_instances,
+        )
+        .unwrap();
+
+        let circuit = mock_circuit(builder, assigned_instances, k, lookup_bits);
+        MockProver::run(k as u32, &circuit, vec![public_io]).unwrap().assert_satisfied();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_edit_and_commit() {
+        let k = 14;
+        let lookup_bits = 13;
+        let (width, height, channels, bit_depth) = (12, 6, 4, 16);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let edits = [
+            util::Edit::Crop { x: 1, y: 1, width: 10, height: 4 },
+            util::Edit::FlattenAlpha { background: vec![65535, 0, 4096] },
+            util::Edit::Crop { x: 2, y: 1, width: 7, height: 3 },
+            util::Edit::ConvertDepth { to_bits: 8 },
+        ];
+        let (edited, w, h, c, d) = util::apply_edits(&image, width, height, channels, bit_depth, &edits).unwrap();
+        assert_eq!((w, h, c, d), (7, 3, 3, 8));
+        let public_io = vec![
+            util::commit_image::<Fr>(&image, width, height, channels, bit_depth).unwrap(),
+            util::commit_image::<Fr>(&edited, w, h, c, d).unwrap(),
+        ];
+
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let range = RangeChip::default(lookup_bits);
+        let mut assigned_instances = vec![];
+        edit_and_commit(
+            &mut builder,
+            &range,
+            &image,
+            width,
+            height,
+            channels,
+            bit_depth,
+            &edits,
+            &mut assigned_instances,
+        )
+        .unwrap();
+
+        let circuit = mock_circuit(builder, assigned_instances, k, lookup_bits);
+        MockProver::run(k as u32, &circuit, vec![public_io]).unwrap().assert_satisfied();
+    }
+
+    #[test]
+    fn test_tiled_commitment() {
+        let k = 12;
+        let lookup_bits = 11;
+        let (width, height, channels, bit_depth) = (12, 8, 3, 8);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let leaves: Vec<Fr> = util::split_tiles(&image, width, height, channels, 4, 4)
+            .unwrap()
+            .iter()
+            .map(|tile| util::commit_image(tile, 4, 4, channels, bit_depth).unwrap())
+            .collect();
+        let expected = util::commit_tiled_image::<Fr>(&image, width, height, channels, bit_depth, 4, 4).unwrap();
+
+        let mut builder = GateThreadBuilder::<Fr>::mock();
+        let gate = GateChip::default();
+        let ctx = builder.main(0);
+        let leaves = ctx.assign_witnesses(leaves);
+        let commitment = tiled_commitment(ctx, &gate, &leaves, width, height, channels, bit_depth, 4, 4);
+        assert_eq!(*commitment.value(), expected);
+
+        let circuit = mock_circuit(builder, vec![commitment], k, lookup_bits);
+        MockProver::run(k as u32, &circuit, vec![vec![expected]]).unwrap().assert_satisfied();
+    }
+
+    #[test]
+    fn test_dynamic_crop_and_commit() {
+        let k = 14;
+        let lookup_bits = 13;
+        let (width, height, channels, bit_depth) = (16, 8, 3, 8);
+        let (w, h) = (9, 5);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+
+        // same circuit shape for every offset, including both extremes
+        for (x, y) in [(0, 0), (3, 2), (width - w, height - h)] {
+            let cropped = util::crop_image(&image, width, channels, x, y, w, h).unwrap();
+            let public_io = vec![
+                util::commit_image::<Fr>(&image, width, height, channels, bit_depth).unwrap(),
+                util::commit_image::<Fr>(&cropped, w, h, channels, bit_depth).unwrap(),
+            ];
+
+            let mut builder = GateThreadBuilder::<Fr>::mock();
+            let range = RangeChip::default(lookup_bits);
+            let mut assigned_instances = vec![];
+            dynamic_crop_and_commit(
+                &mut builder,
+                &range,
+                &image,
+                width,
+                height,
+                channels,
+                bit_depth,
+                x,
+                y,
+                w,
+                h,
+                false,
+                &mut assigned_instances,
+            )
+            .unwrap();
+
+            let circuit = mock_circuit(builder, assigned_instances, k, lookup_bits);
+            MockProver::run(k as u32, &circuit, vec![public_io]).unwrap().assert_satisfied();
+        }
+    }
+
+    #[test]
+    fn test_merkle_crop_and_commit() {
+        let k = 14;
+        let lookup_bits = 13;
+        // 8 leaves of 2 rows; a 5 row crop opens 3 of them
+        let (width, height, channels, bit_depth, rows_per_leaf) = (16, 16, 3, 8, 2);
+        let (w, h) = (9, 5);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let original =
+            util::commit_tiled_image::<Fr>(&image, width, height, channels, bit_depth, width, rows_per_leaf).unwrap();
+
+        // aligned, unaligned, and the last leaves, which are opened from before the crop
+        for (x, y) in [(0, 0), (3, 3), (width - w, height - h)] {
+            let cropped = util::crop_image(&image, width, channels, x, y, w, h).unwrap();
+            let public_io = vec![
+                original,
+                util::commit_image::<Fr>(&cropped, w, h, channels, bit_depth).unwrap(),
+                Fr::from(x as u64),
+                Fr::from(y as u64),
+            ];
+
+            let mut builder = GateThreadBuilder::<Fr>::mock();
+            let range = RangeChip::default(lookup_bits);
+            let mut assigned_instances = vec![];
+            merkle_crop_and_commit(
+                &mut builder,
+                &range,
+                &image,
+                width,
+                height,
+                channels,
+                bit_depth,
+                rows_per_leaf,
+                x,
+                y,
+                w,
+                h,
+                true,
+                &mut assigned_instances,
+            )
+            .unwrap();
+
+            let circuit = mock_circuit(builder, assigned_instances, k, lookup_bits);
+            MockProver::run(k as u32, &circuit, vec![public_io]).unwrap().assert_satisfied();
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::{cell::RefCell, fmt, fs, path::Path};
+
+use halo2_base::{
+    gates::{
+        builder::{
+            FlexGateConfigParams, MultiPhaseThreadBreakPoints, RangeWithInstanceCircuitBuilder,
+            RangeWithInstanceConfig,
+        },
+        range::{RangeConfig, RangeStrategy},
+    },
+    halo2_proofs::{
+        circuit::{Layouter, SimpleFloorPlanner},
+        plonk::{self, Circuit, ConstraintSystem},
+    },
+    utils::ScalarField,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+/// Limb layout of the non-native field chips used by the signature circuit.
+pub const DEFAULT_LIMB_BITS: usize = 88;
+pub const DEFAULT_NUM_LIMBS: usize = 3;
+/// Window size of the scalar multiplications in the signature circuit.
+pub const DEFAULT_WINDOW_BITS: usize = 4;
+
+/// Parameters chosen before keygen and passed to the circuit builders. The
+/// limb and window fields only matter for circuits with elliptic curve chips
+/// and may be left out of the JSON.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitParams {
+    pub k: usize,
+    pub lookup_bits: usize,
+    pub minimum_rows: Option<usize>,
+    #[serde(default = "default_limb_bits")]
+    pub limb_bits: usize,
+    #[serde(default = "default_num_limbs")]
+    pub num_limbs: usize,
+    #[serde(default = "default_window_bits")]
+    pub fixed_window_bits: usize,
+    #[serde(default = "default_window_bits")]
+    pub var_window_bits: usize,
+}
+
+fn default_limb_bits() -> usize {
+    DEFAULT_LIMB_BITS
+}
+
+fn default_num_limbs() -> usize {
+    DEFAULT_NUM_LIMBS
+}
+
+fn default_window_bits() -> usize {
+    DEFAULT_WINDOW_BITS
+}
+
+impl CircuitParams {
+    pub fn new(k: usize, lookup_bits: usize, minimum_rows: Option<usize>) -> Self {
+        Self {
+            k,
+            lookup_bits,
+            minimum_rows,
+            limb_bits: DEFAULT_LIMB_BITS,
+            num_limbs: DEFAULT_NUM_LIMBS,
+            fixed_window_bits: DEFAULT_WINDOW_BITS,
+            var_window_bits: DEFAULT_WINDOW_BITS,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let params: Self = serde_json::from_str(json)?;
+        params.check()?;
+        Ok(params)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// The lookup table takes `2^lookup_bits` rows, so it must fit below `2^k`,
+    /// and the gates need rows left over after `minimum_rows`.
+    pub fn check(&self) -> std::result::Result<(), ConfigError> {
+        if self.k == 0 {
+            return Err(ConfigError::InvalidValue { name: "k".to_string(), value: "0".to_string() });
+        }
+        if self.lookup_bits >= self.k {
+            return Err(ConfigError::InvalidValue {
+                name: "lookup_bits".to_string(),
+                value: format!("{} (k = {})", self.lookup_bits, self.k),
+            });
+        }
+        if let Some(minimum_rows) = self.minimum_rows.filter(|&rows| rows >= 1 << self.k) {
+            return Err(ConfigError::InvalidValue {
+                name: "minimum_rows".to_string(),
+                value: format!("{minimum_rows} (k = {})", self.k),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The circuit shape fixed at keygen. Keygen fills in `gate_params` and
+/// `break_points`; the prover must rebuild the circuit with exactly the same
+/// values, which [`crate::circuit::build_circuit`] checks.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CircuitConfig {
+    #[serde(flatten)]
+    pub params: CircuitParams,
+    pub gate_params: FlexGateConfigParams,
+    pub break_points: MultiPhaseThreadBreakPoints,
+}
+
+#[derive(Clone, Debug)]
+pub enum ConfigError {
+    /// Proving was attempted with a config that never went through keygen.
+    MissingBreakPoints,
+    /// The params or keys are for a different `k` than the config.
+    KMismatch { expected: usize, found: usize },
+    /// The witness generation produced a different number of columns than at
+    /// keygen, e.g. because the image dimensions or edits changed.
+    ShapeMismatch { expected: FlexGateConfigParams, found: FlexGateConfigParams },
+    /// A parameter that is out of range.
+    InvalidValue { name: String, value: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingBreakPoints => {
+                write!(f, "circuit config has no break points; run keygen first")
+            }
+            ConfigError::KMismatch { expected, found } => {
+                write!(f, "circuit config is for k = {expected} but got k = {found}")
+            }
+            ConfigError::ShapeMismatch { expected, found } => write!(
+                f,
+                "circuit shape differs from keygen: expected {expected:?}, found {found:?}"
+            ),
+            ConfigError::InvalidValue { name, value } => write!(f, "invalid value {value:?} for {name}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl CircuitConfig {
+    /// A config before keygen.
+    pub fn new(params: CircuitParams) -> Self {
+        Self {
+            params,
+            gate_params: FlexGateConfigParams::default(),
+            break_points: vec![],
+        }
+    }
+
+    /// Records the config for [`ConfiguredCircuit::configure`], which has no
+    /// `self` to read it from, so this must run on the same thread before
+    /// reading keys, keygen or proving. `configure` panics otherwise.
+    pub fn apply(&self) {
+        APPLIED.with(|applied| *applied.borrow_mut() = Some(self.clone()));
+    }
+
+    pub fn check_k(&self, k: u32) -> std::result::Result<(), ConfigError> {
+        if self.params.k != k as usize {
+            return Err(ConfigError::KMismatch { expected: self.params.k, found: k as usize });
+        }
+        Ok(())
+    }
+
+    /// Checks that `gate_params` computed from the prover's witness generation
+    /// matches the one stored at keygen.
+    pub fn check_shape(&self, gate_params: &FlexGateConfigParams) -> std::result::Result<(), ConfigError> {
+        if self.break_points.is_empty() {
+            return Err(ConfigError::MissingBreakPoints);
+        }
+        // FlexGateConfigParams has no PartialEq
+        if serde_json::to_value(&self.gate_params).unwrap() != serde_json::to_value(gate_params).unwrap() {
+            return Err(ConfigError::ShapeMismatch {
+                expected: self.gate_params.clone(),
+                found: gate_params.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static APPLIED: RefCell<Option<CircuitConfig>> = RefCell::new(None);
+}
+
+/// The config of the last [`CircuitConfig::apply`] on this thread.
+pub fn applied() -> Option<CircuitConfig> {
+    APPLIED.with(|applied| applied.borrow().clone())
+}
+
+/// `RangeWithInstanceCircuitBuilder` configured from [`applied`] rather than
+/// from the environment variables halo2-lib reads. The constraint system is
+/// the same, so keys work with either.
+#[derive(Clone, Debug)]
+pub struct ConfiguredCircuit<F: ScalarField>(pub RangeWithInstanceCircuitBuilder<F>);
+
+impl<F: ScalarField> ConfiguredCircuit<F> {
+    pub fn break_points(&self) -> MultiPhaseThreadBreakPoints {
+        self.0.break_points()
+    }
+
+    pub fn instance(&self) -> Vec<F> {
+        self.0.instance()
+    }
+}
+
+impl<F: ScalarField> From<RangeWithInstanceCircuitBuilder<F>> for ConfiguredCircuit<F> {
+    fn from(circuit: RangeWithInstanceCircuitBuilder<F>) -> Self {
+        Self(circuit)
+    }
+}
+
+impl<F: ScalarField> Circuit<F> for ConfiguredCircuit<F> {
+    type Config = RangeWithInstanceConfig<F>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        // a keygen-stage copy of the builder assigns every witness as unknown
+        let builder = self.0.circuit.0.builder.borrow().clone();
+        Self(RangeWithInstanceCircuitBuilder::keygen(builder, self.0.assigned_instances.clone()))
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        // `configure` has neither `self` nor a way to return an error, and
+        // any layout made up here would only fail later against the keys
+        let config = applied().expect(
+            "ConfiguredCircuit::configure needs a CircuitConfig; call CircuitConfig::apply on this thread first",
+        );
+        let gate_params = config.gate_params;
+        let range = RangeConfig::configure(
+            meta,
+            RangeStrategy::Vertical,
+            &gate_params.num_advice_per_phase,
+            &gate_params.num_lookup_advice_per_phase,
+            gate_params.num_fixed,
+            config.params.lookup_bits,
+            gate_params.k,
+        );
+        let instance = meta.instance_column();
+        meta.enable_equality(instance);
+        RangeWithInstanceConfig { range, instance }
+    }
+
+    fn synthesize(&self, config: Self::Config, layouter: impl Layouter<F>) -> std::result::Result<(), plonk::Error> {
+        self.0.synthesize(config, layouter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params_from_json() {
+        let params = CircuitParams::from_json(r#"{ "k": 13, "lookup_bits": 12, "minimum_rows": 9 }"#).unwrap();
+        assert_eq!(params, CircuitParams::new(13, 12, Some(9)));
+
+        let params = CircuitParams::from_json(r#"{ "k": 13, "lookup_bits": 12, "limb_bits": 64, "num_limbs": 4 }"#).unwrap();
+        assert_eq!((params.minimum_rows, params.limb_bits, params.num_limbs), (None, 64, 4));
+
+        assert!(CircuitParams::from_json(r#"{ "k": 13, "lookup_bits": 13 }"#).is_err());
+        assert!(CircuitParams::new(0, 0, None).check().is_err());
+        assert!(CircuitParams::new(4, 3, Some(16)).check().is_err());
+        assert!(CircuitParams::new(4, 3, Some(15)).check().is_ok());
+    }
+}
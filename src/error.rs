@@ -0,0 +1,102 @@
+use std::{fmt, io};
+
+use halo2_base::halo2_proofs::plonk;
+
+use crate::{bundle::Transcript, config::ConfigError, verify::VerifyError};
+
+/// Errors returned by the public API.
+#[derive(Debug)]
+pub enum Error {
+    /// An image buffer or edit that does not fit the given dimensions.
+    InvalidDimensions(String),
+    UnsupportedBitDepth(usize),
+    /// A transcript this build cannot prove or verify with, e.g. Keccak
+    /// without the `evm` feature.
+    UnsupportedTranscript(Transcript),
+    /// A pixel value that does not fit in the image's bit depth.
+    PixelOutOfRange { index: usize, value: u64, bit_depth: usize },
+    InvalidSignature,
+    /// Hiding keys used without the salt of the original's commitment.
+    MissingSalt,
+    /// A malformed or inconsistent SRS or ceremony transcript.
+    InvalidParams(String),
+    /// A bundle or config that failed to encode or decode.
+    Encoding(String),
+    Config(ConfigError),
+    Keygen(plonk::Error),
+    Proof(plonk::Error),
+    Verify(VerifyError),
+    /// EVM verifier generation or execution failed.
+    Evm(String),
+    /// snark-verifier failed to read or fold a proof being aggregated or
+    /// verified recursively, or the proofs do not fit together.
+    Aggregation(String),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidDimensions(msg) => write!(f, "invalid dimensions: {msg}"),
+            Error::UnsupportedBitDepth(bit_depth) => write!(f, "unsupported bit depth {bit_depth}"),
+            Error::UnsupportedTranscript(transcript) => write!(f, "unsupported transcript {transcript:?}"),
+            Error::PixelOutOfRange { index, value, bit_depth } => {
+                write!(f, "value {value} at index {index} does not fit in {bit_depth} bits")
+            }
+            Error::InvalidSignature => write!(f, "invalid signature"),
+            Error::MissingSalt => write!(f, "hiding keys need the salt of the original's commitment"),
+            Error::InvalidParams(msg) => write!(f, "invalid params: {msg}"),
+            Error::Encoding(msg) => write!(f, "encoding error: {msg}"),
+            Error::Config(e) => write!(f, "{e}"),
+            Error::Keygen(e) => write!(f, "keygen failed: {e:?}"),
+            Error::Proof(e) => write!(f, "proving failed: {e:?}"),
+            Error::Verify(e) => write!(f, "{e}"),
+            Error::Evm(msg) => write!(f, "evm: {msg}"),
+            Error::Aggregation(msg) => write!(f, "aggregation failed: {msg}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(e) => Some(e),
+            Error::Verify(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+impl From<VerifyError> for Error {
+    fn from(e: VerifyError) -> Self {
+        Error::Verify(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Encoding(e.to_string())
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(e: bincode::Error) -> Self {
+        Error::Encoding(e.to_string())
+    }
+}
@@ -0,0 +1,244 @@
+//! Circuit sizing: counts the cells a circuit assigns and works out the
+//! columns `GateThreadBuilder::config` would lay them out in for each k, so k
+//! can be picked before running keygen.
+
+use std::{collections::HashSet, ops::RangeInclusive};
+
+use halo2_base::{
+    gates::{
+        builder::{FlexGateConfigParams, GateThreadBuilder},
+        flex_gate::GateStrategy,
+        RangeChip,
+    },
+    halo2_proofs::halo2curves::bn256::Fr,
+    utils::ScalarField,
+    Context,
+};
+
+use crate::{
+    circuit::edit_and_commit,
+    config::{CircuitParams, ConfigError},
+    error::Result,
+    util::{self, Edit},
+};
+
+/// Cells assigned by a circuit, summed over all threads of every phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CircuitStats {
+    pub advice_cells: usize,
+    pub lookup_cells: usize,
+    /// Distinct constants, each of which takes a fixed cell.
+    pub fixed_cells: usize,
+}
+
+impl CircuitStats {
+    pub fn from_builder<F: ScalarField>(builder: &GateThreadBuilder<F>) -> Self {
+        let threads = || builder.threads.iter().flatten();
+        let constants: HashSet<F> = threads()
+            .flat_map(|ctx| ctx.constant_equality_constraints.iter().map(|(c, _)| *c))
+            .collect();
+        Self {
+            advice_cells: threads().map(|ctx| ctx.advice.len()).sum(),
+            lookup_cells: threads().map(|ctx| ctx.cells_to_lookup.len()).sum(),
+            fixed_cells: constants.len(),
+        }
+    }
+}
+
+/// The layout of a circuit at `params.k`, matching `GateThreadBuilder::config`.
+/// That lays out each phase in its own columns, so with cells in later phases
+/// this can be up to a column short per phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeEstimate {
+    pub params: CircuitParams,
+    pub stats: CircuitStats,
+    pub num_advice: usize,
+    pub num_lookup_advice: usize,
+    pub num_fixed: usize,
+}
+
+impl SizeEstimate {
+    pub fn new(params: CircuitParams, stats: CircuitStats) -> Self {
+        let max_rows = (1 << params.k) - params.minimum_rows.unwrap_or(0);
+        let columns = |cells: usize, rows: usize| (cells + rows - 1) / rows;
+        Self {
+            params,
+            stats,
+            num_advice: columns(stats.advice_cells, max_rows),
+            num_lookup_advice: columns(stats.lookup_cells, max_rows),
+            // constants may use every row
+            num_fixed: columns(stats.fixed_cells, 1 << params.k),
+        }
+    }
+
+    /// Columns the prover commits to. Proof size and verification time grow
+    /// with this.
+    pub fn columns(&self) -> usize {
+        self.num_advice + self.num_lookup_advice + self.num_fixed
+    }
+
+    /// Rows times columns, which proving time roughly follows.
+    pub fn prover_cells(&self) -> u64 {
+        (1u64 << self.params.k) * self.columns() as u64
+    }
+}
+
+/// The same layout as `GateThreadBuilder::config`, which also writes it to the
+/// `FLEX_GATE_CONFIG_PARAMS` environment variable. Circuits here are configured
+/// through [`crate::config::CircuitConfig::apply`] instead, so the builders use
+/// this and leave the environment alone.
+pub fn gate_params<F: ScalarField>(
+    builder: &GateThreadBuilder<F>,
+    k: usize,
+    minimum_rows: Option<usize>,
+) -> FlexGateConfigParams {
+    let max_rows = (1 << k) - minimum_rows.unwrap_or(0);
+    let columns = |cells: usize, rows: usize| (cells + rows - 1) / rows;
+    let per_phase = |cells: fn(&Context<F>) -> usize| {
+        builder.threads.iter().map(|threads| columns(threads.iter().map(cells).sum(), max_rows)).collect()
+    };
+    let constants: HashSet<F> = builder
+        .threads
+        .iter()
+        .flatten()
+        .flat_map(|ctx| ctx.constant_equality_constraints.iter().map(|(c, _)| *c))
+        .collect();
+    FlexGateConfigParams {
+        strategy: GateStrategy::Vertical,
+        k,
+        num_advice_per_phase: per_phase(|ctx| ctx.advice.len()),
+        num_lookup_advice_per_phase: per_phase(|ctx| ctx.cells_to_lookup.len()),
+        num_fixed: columns(constants.len(), 1 << k),
+    }
+}
+
+/// What to optimize when choosing k.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The smallest k whose layout needs at most this many columns.
+    MaxColumns(usize),
+    /// The k with the least proving work; ties go to the larger k, which gives
+    /// the smaller proof.
+    MinProvingWork,
+}
+
+/// Builds the circuit with `build` in keygen mode and sizes it for `params`.
+pub fn estimate<F: ScalarField>(
+    params: CircuitParams,
+    build: impl Fn(&mut GateThreadBuilder<F>, &CircuitParams) -> Result<()>,
+) -> Result<SizeEstimate> {
+    params.check()?;
+    let mut builder = GateThreadBuilder::keygen();
+    build(&mut builder, &params)?;
+    Ok(SizeEstimate::new(params, CircuitStats::from_builder(&builder)))
+}
+
+/// Estimates every k in `ks` with `lookup_bits = k - 1` and picks one for
+/// `target`. The circuit is rebuilt for each k since range checks decompose
+/// into `lookup_bits` sized limbs.
+pub fn choose_k<F: ScalarField>(
+    ks: RangeInclusive<usize>,
+    minimum_rows: Option<usize>,
+    target: Target,
+    build: impl Fn(&mut GateThreadBuilder<F>, &CircuitParams) -> Result<()>,
+) -> Result<SizeEstimate> {
+    let mut best: Option<SizeEstimate> = None;
+    for k in ks.clone() {
+        // `estimate` rejects k = 0 before anything uses the lookup bits
+        let size = estimate(CircuitParams::new(k, k.saturating_sub(1), minimum_rows), &build)?;
+        log::debug!("k = {k}: {} columns, {} prover cells", size.columns(), size.prover_cells());
+        match target {
+            Target::MaxColumns(max) if size.columns() <= max => return Ok(size),
+            Target::MaxColumns(_) => {}
+            Target::MinProvingWork => {
+                if best.map_or(true, |best| size.prover_cells() <= best.prover_cells()) {
+                    best = Some(size);
+                }
+            }
+        }
+    }
+    best.ok_or_else(|| {
+        ConfigError::InvalidValue {
+            name: "k".to_string(),
+            value: format!("no k in {ks:?} meets {target:?}"),
+        }
+        .into()
+    })
+}
+
+/// Picks k for [`edit_and_commit`] on images of the given shape. Only the
+/// shape matters, so the circuit is built on a blank image.
+pub fn choose_k_for_edits(
+    width: usize,
+    height: usize,
+    channels: usize,
+    bit_depth: usize,
+    edits: &[Edit],
+    ks: RangeInclusive<usize>,
+    minimum_rows: Option<usize>,
+    target: Target,
+) -> Result<SizeEstimate> {
+    util::check_bit_depth(bit_depth)?;
+    let blank = vec![0; width * height * channels];
+    choose_k::<Fr>(ks, minimum_rows, target, |builder, params| {
+        let range = RangeChip::default(params.lookup_bits);
+        edit_and_commit(
+            builder,
+            &range,
+            &blank,
+            width,
+            height,
+            channels,
+            bit_depth,
+            edits,
+            &mut vec![],
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_all_phases() {
+        let mut builder = GateThreadBuilder::<Fr>::keygen();
+        builder.main(0).load_witness(Fr::from(1));
+        builder.main(1).load_witness(Fr::from(2));
+        builder.main(1).load_constant(Fr::from(3));
+        let stats = CircuitStats::from_builder(&builder);
+        assert_eq!((stats.advice_cells, stats.lookup_cells, stats.fixed_cells), (3, 0, 1));
+    }
+
+    #[test]
+    fn test_choose_k() {
+        let edits = [Edit::Crop { x: 3, y: 2, width: 9, height: 5 }];
+        let image = vec![0; 16 * 8 * 3];
+        let build = |builder: &mut GateThreadBuilder<Fr>, params: &CircuitParams| {
+            let range = RangeChip::default(params.lookup_bits);
+            edit_and_commit(builder, &range, &image, 16, 8, 3, 8, &edits, &mut vec![])
+        };
+
+        let smallest = choose_k_for_edits(16, 8, 3, 8, &edits, 10..=16, Some(9), Target::MaxColumns(4)).unwrap();
+        assert!(smallest.columns() <= 4);
+        if smallest.params.k > 10 {
+            let k = smallest.params.k - 1;
+            let smaller = estimate(CircuitParams::new(k, k - 1, Some(9)), build).unwrap();
+            assert!(smaller.columns() > 4);
+        }
+        assert!(choose_k_for_edits(16, 8, 3, 8, &edits, 0..=16, Some(9), Target::MinProvingWork).is_err());
+
+        // the layout matches what halo2-lib computes for the same circuit
+        let params = smallest.params;
+        let mut builder = GateThreadBuilder::<Fr>::keygen();
+        build(&mut builder, &params).unwrap();
+        let gate_params = builder.config(params.k, params.minimum_rows);
+        assert_eq!(gate_params.num_advice_per_phase[0], smallest.num_advice);
+        assert_eq!(gate_params.num_lookup_advice_per_phase[0], smallest.num_lookup_advice);
+        assert_eq!(gate_params.num_fixed, smallest.num_fixed);
+        assert_eq!(
+            serde_json::to_value(gate_params).unwrap(),
+            serde_json::to_value(super::gate_params(&builder, params.k, params.minimum_rows)).unwrap()
+        );
+    }
+}
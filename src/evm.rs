@@ -0,0 +1,153 @@
+//! Solidity verifier generation with snark-verifier's EVM loader, built with
+//! the `evm` feature. The contract replays a Keccak transcript, so proofs for
+//! it must be made with [`crate::bundle::Transcript::Keccak`]. Calldata is the
+//! public instances as 32 byte big-endian words followed by the proof.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    rc::Rc,
+};
+
+use halo2_base::halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fq, Fr, G1Affine},
+    plonk::VerifyingKey,
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+};
+use snark_verifier::{
+    loader::evm::{self, Address, EvmLoader, ExecutorBuilder},
+    pcs::kzg::{Bdfg21, KzgAs, KzgDecidingKey},
+    system::halo2::{compile, transcript::evm::EvmTranscript, Config},
+    verifier::{self, SnarkVerifier},
+};
+
+use crate::error::{Error, Result};
+
+/// SHPLONK, the multi-open scheme the prover uses.
+type PlonkVerifier = verifier::plonk::PlonkVerifier<KzgAs<Bn256, Bdfg21>>;
+
+/// Solidity source of a contract that verifies proofs for `vk` with
+/// `num_instance` public instances in its single instance column.
+pub fn gen_solidity_verifier(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    num_instance: usize,
+) -> Result<String> {
+    let protocol = compile(params, vk, Config::kzg().with_num_instance(vec![num_instance]));
+    let dk: KzgDecidingKey<Bn256> = (params.get_g()[0], params.g2(), params.s_g2()).into();
+
+    let loader = EvmLoader::new::<Fq, Fr>();
+    let protocol = protocol.loaded(&loader);
+    let mut transcript = EvmTranscript::<_, Rc<EvmLoader>, _, _>::new(&loader);
+    let instances = transcript.load_instances(vec![num_instance]);
+    let proof = PlonkVerifier::read_proof(&dk, &protocol, &instances, &mut transcript)
+        .map_err(|e| Error::Evm(format!("{e:?}")))?;
+    PlonkVerifier::verify(&dk, &protocol, &instances, &proof).map_err(|e| Error::Evm(format!("{e:?}")))?;
+    Ok(loader.solidity_code())
+}
+
+/// Deployment bytecode of a contract from [`gen_solidity_verifier`]. Runs
+/// `solc`, which must be on the `PATH`.
+pub fn compile_solidity(code: &str) -> Result<Vec<u8>> {
+    let mut solc = Command::new("solc")
+        .args(["--bin", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Evm(format!("cannot run solc: {e}")))?;
+    solc.stdin.take().expect("solc stdin is piped").write_all(code.as_bytes())?;
+    let output = solc.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::Evm(format!("solc failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    // the hex bytecode is on the line after "Binary:"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let bytecode = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("Binary"))
+        .nth(1)
+        .ok_or_else(|| Error::Evm("no bytecode in the solc output".to_string()))?;
+    hex::decode(bytecode.trim()).map_err(|e| Error::Evm(format!("solc bytecode: {e}")))
+}
+
+pub fn encode_calldata(instances: &[Fr], proof: &[u8]) -> Vec<u8> {
+    evm::encode_calldata(&[instances.to_vec()], proof)
+}
+
+/// Deploys the verifier in a local revm instance and calls it with the proof.
+/// Returns the gas used by the call.
+pub fn evm_verify(deployment_code: Vec<u8>, instances: &[Fr], proof: &[u8]) -> Result<u64> {
+    let calldata = encode_calldata(instances, proof);
+    let mut evm = ExecutorBuilder::default().with_gas_limit(u64::MAX.into()).build();
+    let caller = Address::from_low_u64_be(0xfe);
+    let verifier = evm
+        .deploy(caller, deployment_code.into(), 0.into())
+        .address
+        .ok_or_else(|| Error::Evm("verifier deployment failed".to_string()))?;
+    let result = evm.call_raw(caller, verifier, calldata.into(), 0.into());
+    if result.reverted {
+        return Err(Error::Evm("verifier reverted".to_string()));
+    }
+    Ok(result.gas_used)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bundle::{CircuitId, Transcript},
+        config::{CircuitConfig, CircuitParams, ConfiguredCircuit},
+        keys::{CircuitSpec, CROP_CIRCUIT},
+        prover::keygen,
+        srs::SrsManager,
+        util,
+        verify::verify,
+    };
+    use halo2_base::gates::builder::CircuitBuilderStage;
+
+    #[test]
+    fn test_evm_verifier() {
+        let k = 13;
+        let spec = CircuitSpec {
+            circuit: CircuitId::new(CROP_CIRCUIT),
+            width: 16,
+            height: 8,
+            channels: 3,
+            bit_depth: 8,
+            crop_width: 9,
+            crop_height: 5,
+            reveal_offset: true,
+            rows_per_leaf: None,
+            hiding: false,
+        };
+        let mut config = CircuitConfig::new(CircuitParams::new(k, 12, Some(9)));
+        let params = SrsManager::new("params").dev_mode(true).load(k as u32).unwrap();
+        let circuit = spec.circuit(CircuitBuilderStage::Keygen, &spec.blank(), (0, 0), None, &mut config).unwrap();
+        let pk = keygen(&params, &circuit).unwrap();
+        config.break_points = circuit.break_points();
+
+        let image = util::generate_image(16, 8, 3, 8).unwrap();
+        let (bundle, _) = spec
+            .prove::<ConfiguredCircuit<Fr>>(
+                &params,
+                &pk,
+                &mut config,
+                &image,
+                (3, 2),
+                None,
+                Transcript::Keccak,
+            )
+            .unwrap();
+        verify(&params, pk.get_vk(), &spec, &bundle).unwrap();
+
+        let code = gen_solidity_verifier(&params, pk.get_vk(), spec.num_instance()).unwrap();
+        let deployment_code = compile_solidity(&code).unwrap();
+        evm_verify(deployment_code.clone(), &bundle.instances, &bundle.proof).unwrap();
+
+        // a different crop offset than the one proven
+        let mut instances = bundle.instances.clone();
+        instances[2] = Fr::from(4);
+        assert!(evm_verify(deployment_code, &instances, &bundle.proof).is_err());
+    }
+}
@@ -0,0 +1,475 @@
+//! Recursive edit history, after snark-verifier's recursion example. Each edit
+//! of a chain is proven by the history circuit, which verifies the edit's
+//! proof and the chain's previous history proof in-circuit. A chain of any
+//! length is then carried by one proof exposing only the original and latest
+//! image commitments and the number of edits; the images in between stay
+//! private. The original commitment is what the original owner signs, so a
+//! [`crate::util::Signature`] checks against the chain as against one edit.
+//!
+//! The circuit verifies proofs of itself, so its verifying key cannot be a
+//! constant of the circuit. The previous proof's fixed commitments are
+//! witnesses instead, hashed into a digest carried along the chain as a public
+//! instance, and [`crate::verify::verify_history`] checks the digest against
+//! the key it trusts. The first edit of a chain verifies a dummy previous
+//! proof whose accumulators are swapped for a constant one.
+//!
+//! Edit proofs come from one edit key directory and must use
+//! [`Transcript::Poseidon`], like those for [`crate::aggregation`].
+
+use std::{fs, path::Path, rc::Rc};
+
+use halo2_base::{
+    gates::{
+        builder::{CircuitBuilderStage, GateThreadBuilder, RangeWithInstanceConfig},
+        GateInstructions, RangeChip, RangeInstructions,
+    },
+    halo2_proofs::{
+        arithmetic::Field,
+        circuit::{Layouter, SimpleFloorPlanner},
+        halo2curves::{
+            bn256::{Bn256, Fr, G1Affine, G1},
+            group::{Curve, Group},
+        },
+        plonk::{self, keygen_vk, Circuit, ConstraintSystem, ProvingKey, VerifyingKey},
+        poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    },
+    AssignedValue,
+    QuantumCell::Constant,
+};
+use halo2_ecc::{bn254::FpChip, ecc::BaseFieldEccChip};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use snark_verifier::{
+    cost::CostEstimation,
+    loader::{native::NativeLoader, EcPointLoader},
+    pcs::kzg::KzgAccumulator,
+    util::{arithmetic::fe_to_limbs, transcript::TranscriptWrite},
+    verifier::plonk::{PlonkProof, PlonkProtocol},
+};
+
+use crate::{
+    aggregation::{
+        accumulator_limbs, fold, fold_native, snark_error, succinct_verify, succinct_verify_native, As, Halo2Loader,
+        LoadedAccumulator, Snark, Svk,
+    },
+    bundle::{CircuitId, HistoryBundle, Transcript},
+    circuit::{build_circuit, poseidon_hash},
+    config::{CircuitConfig, ConfiguredCircuit},
+    error::{Error, Result},
+    keys::CircuitSpec,
+    prover::{gen_proof_with, keygen},
+    verify::{
+        history_protocol, preprocessed_digest, verify_history, PoseidonTranscript, ACCUMULATOR_LIMBS,
+        ACCUMULATOR_LIMB_BITS, HISTORY_DIGEST_ROW, HISTORY_EDITS_ROW, HISTORY_LATEST_ROW, HISTORY_ORIGINAL_ROW,
+    },
+};
+
+const HISTORY_SPEC_FILE: &str = "history.json";
+/// Circuit id of [`HistorySpec`].
+pub const HISTORY_CIRCUIT: &str = "history";
+/// Rounds keygen gets for the circuit shape to settle, see
+/// [`HistorySpec::keygen`].
+const MAX_KEYGEN_ROUNDS: usize = 4;
+
+/// The previous history proof of a chain, or the dummy the first edit
+/// verifies in its place.
+struct Previous {
+    protocol: PlonkProtocol<G1Affine>,
+    instances: Vec<Fr>,
+    proof: Vec<u8>,
+}
+
+impl Previous {
+    fn new(
+        params: &ParamsKZG<Bn256>,
+        vk: &VerifyingKey<G1Affine>,
+        inner: &CircuitSpec,
+        bundle: &HistoryBundle,
+    ) -> Result<Self> {
+        verify_history(params, vk, inner, bundle)?;
+        let protocol = history_protocol(params, vk);
+        Ok(Self { protocol, instances: bundle.instances.clone(), proof: bundle.proof.clone() })
+    }
+
+    /// Only the shape of `protocol` matters here. The instances hold the
+    /// default accumulator and zeros, which the first-edit constraints expect,
+    /// and the proof is random points and scalars of the right count.
+    fn dummy(params: &ParamsKZG<Bn256>, protocol: PlonkProtocol<G1Affine>) -> Result<Self> {
+        let g = params.get_g();
+        let instances = [g[1].x, g[1].y, g[0].x, g[0].y]
+            .into_iter()
+            .flat_map(fe_to_limbs::<_, _, ACCUMULATOR_LIMBS, ACCUMULATOR_LIMB_BITS>)
+            .chain([Fr::zero(); 4])
+            .collect();
+
+        let mut transcript = PoseidonTranscript::<NativeLoader, _>::new(vec![]);
+        let num_witness = protocol.num_witness.iter().sum::<usize>() + protocol.quotient.num_chunk();
+        for _ in 0..num_witness {
+            transcript.write_ec_point(G1::random(OsRng).to_affine()).map_err(snark_error)?;
+        }
+        for _ in 0..protocol.evaluations.len() {
+            transcript.write_scalar(Fr::random(OsRng)).map_err(snark_error)?;
+        }
+        let queries = PlonkProof::<G1Affine, NativeLoader, As>::empty_queries(&protocol);
+        for _ in 0..As::estimate_cost(&queries).num_commitment {
+            transcript.write_ec_point(G1::random(OsRng).to_affine()).map_err(snark_error)?;
+        }
+        Ok(Self { protocol, instances, proof: transcript.finalize() })
+    }
+}
+
+/// Configures like [`ConfiguredCircuit`] from the applied config
+/// and only enables every selector on the first row, so that keygen keeps
+/// them apart as the real circuit does. Gives the shape of the history key
+/// before the history circuit can be built.
+struct ShapeProxy;
+
+impl Circuit<Fr> for ShapeProxy {
+    type Config = RangeWithInstanceConfig<Fr>;
+    type FloorPlanner = SimpleFloorPlanner;
+
+    fn without_witnesses(&self) -> Self {
+        ShapeProxy
+    }
+
+    fn configure(meta: &mut ConstraintSystem<Fr>) -> Self::Config {
+        ConfiguredCircuit::<Fr>::configure(meta)
+    }
+
+    fn synthesize(&self, config: Self::Config, mut layouter: impl Layouter<Fr>) -> std::result::Result<(), plonk::Error> {
+        layouter.assign_region(
+            || "selectors",
+            |mut region| {
+                for gate in config.range.gate.basic_gates.iter().flatten() {
+                    gate.q_enable.enable(&mut region, 0)?;
+                }
+                for q_lookup in config.range.q_lookup.iter().flatten() {
+                    q_lookup.enable(&mut region, 0)?;
+                }
+                Ok(())
+            },
+        )
+    }
+}
+
+/// What a history key directory proves: chains of edits each proven with the
+/// keys of `inner`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistorySpec {
+    pub circuit: CircuitId,
+    pub inner: CircuitSpec,
+}
+
+impl HistorySpec {
+    pub fn new(inner: CircuitSpec) -> Self {
+        Self { circuit: CircuitId::new(HISTORY_CIRCUIT), inner }
+    }
+
+    /// Reads the spec written next to the history keys.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(dir.as_ref().join(HISTORY_SPEC_FILE))?)?)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(dir.as_ref().join(HISTORY_SPEC_FILE), serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Keygen for the history circuit. The circuit's size depends on the
+    /// shape of the key it verifies, which is its own, so it is rebuilt
+    /// against the shape from the last round until its columns stop changing.
+    /// The first guess is the shape of the edit key. `inner_params`,
+    /// `inner_pk` and `inner_config` are the edit keys' and prove the blank
+    /// edit keygen runs on.
+    pub fn keygen(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        inner_params: &ParamsKZG<Bn256>,
+        inner_pk: &ProvingKey<G1Affine>,
+        inner_config: &mut CircuitConfig,
+        config: &mut CircuitConfig,
+    ) -> Result<ProvingKey<G1Affine>> {
+        let edit = Snark::blank(inner_params, inner_pk, inner_config, &self.inner)?;
+        let mut protocol = history_protocol(inner_params, inner_pk.get_vk());
+        let mut shape = None;
+        for _ in 0..MAX_KEYGEN_ROUNDS {
+            let previous = Previous::dummy(params, protocol)?;
+            let circuit =
+                self.circuit(CircuitBuilderStage::Keygen, params, &edit, &previous, Fr::zero(), 1, config)?;
+            let gate_params = serde_json::to_value(&config.gate_params)?;
+            if shape.as_ref() == Some(&gate_params) {
+                let pk = keygen(params, &circuit)?;
+                config.break_points = circuit.break_points();
+                return Ok(pk);
+            }
+            shape = Some(gate_params);
+            // build_circuit applied the new shape, which ShapeProxy configures from
+            protocol = history_protocol(params, &keygen_vk(params, &ShapeProxy).map_err(Error::Keygen)?);
+        }
+        Err(Error::Aggregation(format!("history circuit shape did not settle in {MAX_KEYGEN_ROUNDS} rounds")))
+    }
+
+    /// Proves `edit` as the next edit of the chain whose latest proof is
+    /// `previous`, or as the first edit of a new chain. The edit must start
+    /// from the chain's latest image.
+    pub fn prove<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        edit: &Snark,
+        previous: Option<&HistoryBundle>,
+    ) -> Result<HistoryBundle> {
+        let vk = pk.get_vk();
+        let protocol = history_protocol(params, vk);
+        let digest = preprocessed_digest(&protocol);
+        let statement = edit.statement();
+        let (previous, edits, original) = match previous {
+            Some(bundle) => {
+                if bundle.latest != statement.original {
+                    return Err(Error::Aggregation(format!(
+                        "edit starts from {:?}, the chain is at {:?}",
+                        statement.original.commitment, bundle.latest.commitment
+                    )));
+                }
+                (Previous::new(params, vk, &self.inner, bundle)?, bundle.edits + 1, bundle.original.clone())
+            }
+            None => (Previous::dummy(params, protocol)?, 1, statement.original.clone()),
+        };
+
+        let circuit = self.circuit(CircuitBuilderStage::Prover, params, edit, &previous, digest, edits, config)?;
+        let instances = circuit.instance();
+        let proof = gen_proof_with(params, pk, C::from(circuit), &instances, Transcript::Poseidon)?;
+        Ok(HistoryBundle::new(
+            self.circuit.clone(),
+            vk,
+            edits,
+            original,
+            statement.result.clone(),
+            instances,
+            proof,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn circuit(
+        &self,
+        stage: CircuitBuilderStage,
+        params: &ParamsKZG<Bn256>,
+        edit: &Snark,
+        previous: &Previous,
+        digest: Fr,
+        edits: usize,
+        config: &mut CircuitConfig,
+    ) -> Result<ConfiguredCircuit<Fr>> {
+        let g = params.get_g();
+        let svk: Svk = g[0].into();
+        let first_edit = edits == 1;
+
+        let mut accumulators = succinct_verify_native(&svk, &edit.protocol, &edit.instances, &edit.proof)?;
+        if first_edit {
+            let default = KzgAccumulator::<G1Affine, NativeLoader>::new(g[1], g[0]);
+            accumulators.extend(vec![default; 1 + previous.protocol.accumulator_indices.len()]);
+        } else {
+            accumulators.extend(succinct_verify_native(
+                &svk,
+                &previous.protocol,
+                &previous.instances,
+                &previous.proof,
+            )?);
+        }
+        let as_proof = fold_native(&accumulators)?;
+        let original = if first_edit { edit.instances[0] } else { previous.instances[HISTORY_ORIGINAL_ROW] };
+
+        let mut builder = GateThreadBuilder::from_stage(stage);
+        let range = RangeChip::<Fr>::default(config.params.lookup_bits);
+        let gate = range.gate();
+        let ctx = builder.main(0);
+        let [digest, original, latest, edits] =
+            [digest, original, edit.instances[1], Fr::from(edits as u64)].map(|value| ctx.load_witness(value));
+        let first_edit = gate.is_equal(ctx, edits, Constant(Fr::one()));
+        let not_first_edit = gate.not(ctx, first_edit);
+
+        let fp_chip = FpChip::<Fr>::new(&range, ACCUMULATOR_LIMB_BITS, ACCUMULATOR_LIMBS);
+        let loader = Halo2Loader::new(BaseFieldEccChip::new(&fp_chip), builder);
+        let protocol = edit.protocol.loaded(&loader);
+        let (edit_instances, edit_accumulators) =
+            succinct_verify(&svk, &loader, &protocol, &edit.instances, &edit.proof)?;
+
+        // the previous proof is of this circuit, so its key is a witness and
+        // only bound to the rest of the chain through its digest
+        let protocol = previous.protocol.loaded_preprocessed_as_witness(&loader);
+        let mut key = vec![];
+        for point in &protocol.preprocessed {
+            let point = point.assigned();
+            key.extend([*point.x().native(), *point.y().native()]);
+        }
+        key.extend(protocol.transcript_initial_state.clone().map(|state| state.into_assigned()));
+        let (previous_instances, previous_accumulators) =
+            succinct_verify(&svk, &loader, &protocol, &previous.instances, &previous.proof)?;
+
+        let default = KzgAccumulator::new(loader.ec_point_load_const(&g[1]), loader.ec_point_load_const(&g[0]));
+        let previous_accumulators = previous_accumulators
+            .iter()
+            .map(|accumulator| select_accumulator(&loader, first_edit, &default, accumulator))
+            .collect::<Vec<_>>();
+        let accumulator = fold(&loader, &[edit_accumulators, previous_accumulators].concat(), &as_proof)?;
+        let mut assigned_instances = accumulator_limbs(accumulator);
+
+        let mut builder = loader.take_ctx();
+        let ctx = builder.main(0);
+        let key_digest = poseidon_hash(ctx, gate, &key);
+        ctx.constrain_equal(&key_digest, &digest);
+        let next_edit = gate.add(ctx, previous_instances[HISTORY_EDITS_ROW], Constant(Fr::one()));
+        for (lhs, rhs) in [
+            // the previous proof carries the same key digest and original
+            (gate.mul(ctx, digest, not_first_edit), previous_instances[HISTORY_DIGEST_ROW]),
+            (gate.mul(ctx, original, not_first_edit), previous_instances[HISTORY_ORIGINAL_ROW]),
+            // a new chain starts from the image the edit starts from
+            (gate.mul(ctx, original, first_edit), gate.mul(ctx, edit_instances[0], first_edit)),
+            // the edit continues from the previous latest image
+            (gate.mul(ctx, edit_instances[0], not_first_edit), previous_instances[HISTORY_LATEST_ROW]),
+            (latest, edit_instances[1]),
+            (edits, next_edit),
+        ] {
+            ctx.constrain_equal(&lhs, &rhs);
+        }
+        assigned_instances.extend([digest, original, latest, edits]);
+        build_circuit(stage, builder, assigned_instances, config)
+    }
+}
+
+/// `default` on the first edit of a chain, whose previous proof is a dummy,
+/// and `accumulator` otherwise.
+fn select_accumulator<'a>(
+    loader: &Rc<Halo2Loader<'a>>,
+    first_edit: AssignedValue<Fr>,
+    default: &LoadedAccumulator<'a>,
+    accumulator: &LoadedAccumulator<'a>,
+) -> LoadedAccumulator<'a> {
+    let [lhs, rhs] = [(&default.lhs, &accumulator.lhs), (&default.rhs, &accumulator.rhs)].map(|(default, point)| {
+        let selected = loader.ecc_chip().select(
+            loader.ctx_mut().main(0),
+            default.assigned().clone(),
+            point.assigned().clone(),
+            first_edit,
+        );
+        loader.ec_point_from_assigned(selected)
+    });
+    KzgAccumulator::new(lhs, rhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bundle::ImageCommitment,
+        config::CircuitParams,
+        keys::CROP_CIRCUIT,
+        srs::SrsManager,
+        util,
+        verify::VerifyError,
+    };
+
+    #[test]
+    fn test_edit_chain() {
+        let (inner_k, k) = (13, 22);
+        // crops to the full frame, so every edit of the chain fits the keys
+        let inner = CircuitSpec {
+            circuit: CircuitId::new(CROP_CIRCUIT),
+            width: 8,
+            height: 4,
+            channels: 3,
+            bit_depth: 8,
+            crop_width: 8,
+            crop_height: 4,
+            reveal_offset: false,
+            rows_per_leaf: None,
+            hiding: false,
+        };
+        let params = SrsManager::new("params").dev_mode(true).load(k as u32).unwrap();
+        let mut inner_params = params.clone();
+        inner_params.downsize(inner_k as u32);
+
+        let mut inner_config = CircuitConfig::new(CircuitParams::new(inner_k, 12, Some(9)));
+        let circuit =
+            inner.circuit(CircuitBuilderStage::Keygen, &inner.blank(), (0, 0), None, &mut inner_config).unwrap();
+        let inner_pk = keygen(&inner_params, &circuit).unwrap();
+        inner_config.break_points = circuit.break_points();
+
+        let spec = HistorySpec::new(inner.clone());
+        let mut config = CircuitConfig::new(CircuitParams::new(k, k - 1, Some(20)));
+        let pk = spec.keygen(&params, &inner_params, &inner_pk, &mut inner_config, &mut config).unwrap();
+
+        let prove_edit = |image: &util::Image, inner_config: &mut CircuitConfig| {
+            let (bundle, _) = inner
+                .prove::<ConfiguredCircuit<Fr>>(
+                    &inner_params,
+                    &inner_pk,
+                    inner_config,
+                    image,
+                    (0, 0),
+                    None,
+                    Transcript::Poseidon,
+                )
+                .unwrap();
+            Snark::new(&inner_params, inner_pk.get_vk(), &inner, &bundle).unwrap()
+        };
+        let image = util::generate_image(8, 4, 3, 8).unwrap();
+        let commitment = ImageCommitment {
+            width: 8,
+            height: 4,
+            channels: 3,
+            bit_depth: 8,
+            commitment: util::commit_image(&image, 8, 4, 3, 8).unwrap(),
+        };
+
+        let first = spec
+            .prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &prove_edit(&image, &mut inner_config), None)
+            .unwrap();
+        verify_history(&params, pk.get_vk(), &inner, &first).unwrap();
+        assert_eq!((first.edits, &first.original, &first.latest), (1, &commitment, &commitment));
+
+        // the second edit is proven on its own, from the chain's latest image
+        let edit = prove_edit(&image, &mut inner_config);
+        let second = spec
+            .prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &edit, Some(&first))
+            .unwrap();
+        verify_history(&params, pk.get_vk(), &inner, &second).unwrap();
+        assert_eq!((second.edits, &second.original, &second.latest), (2, &commitment, &commitment));
+        assert_eq!(second.proof.len(), first.proof.len());
+
+        let other = util::generate_image(8, 4, 3, 8).unwrap();
+        let other_commitment = util::commit_image(&other, 8, 4, 3, 8).unwrap();
+        let mut forged = second.clone();
+        forged.edits = 1;
+        assert!(matches!(
+            verify_history(&params, pk.get_vk(), &inner, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+        let mut forged = second.clone();
+        forged.latest.commitment = other_commitment;
+        assert!(matches!(
+            verify_history(&params, pk.get_vk(), &inner, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+        let mut forged = second.clone();
+        forged.original.commitment = other_commitment;
+        assert!(matches!(
+            verify_history(&params, pk.get_vk(), &inner, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+        // the shapes are not instances, the edit keys fix them
+        let mut forged = second.clone();
+        (forged.latest.width, forged.latest.height) = (4, 8);
+        assert!(matches!(
+            verify_history(&params, pk.get_vk(), &inner, &forged),
+            Err(VerifyError::StatementMismatch)
+        ));
+
+        // an edit of some other image does not continue the chain
+        let other = prove_edit(&other, &mut inner_config);
+        assert!(spec
+            .prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &other, Some(&second))
+            .is_err());
+    }
+}
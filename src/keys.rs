@@ -0,0 +1,456 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use halo2_base::halo2_proofs::{
+    arithmetic::Field,
+    halo2curves::bn256::{Fr, G1Affine},
+    plonk::{Circuit, ProvingKey, VerifyingKey},
+    SerdeFormat,
+};
+#[cfg(feature = "prover")]
+use halo2_base::{
+    gates::{
+        builder::{CircuitBuilderStage, GateThreadBuilder},
+        RangeChip,
+    },
+    halo2_proofs::{halo2curves::bn256::Bn256, poly::kzg::commitment::ParamsKZG},
+    AssignedValue,
+};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bundle::{hex_fr, CircuitId, ImageCommitment, Statement, Transformation},
+    config::{CircuitConfig, ConfigError},
+    error::{Error, Result},
+    util::{self, Image},
+};
+#[cfg(feature = "prover")]
+use crate::{
+    bundle::{ProofBundle, Transcript},
+    circuit::{build_circuit, dynamic_crop_and_commit, hiding_commitment, merkle_crop_and_commit},
+    config::{CircuitParams, ConfiguredCircuit},
+    prover::gen_proof_with,
+};
+
+const PK_FILE: &str = "pk.bin";
+const VK_FILE: &str = "vk.bin";
+const CONFIG_FILE: &str = "config.json";
+const SPEC_FILE: &str = "circuit.json";
+/// Circuit id of [`CircuitSpec`].
+pub const CROP_CIRCUIT: &str = "dynamic_crop";
+/// Circuit id of a [`CircuitSpec`] with [`CircuitSpec::rows_per_leaf`] set.
+pub const MERKLE_CROP_CIRCUIT: &str = "merkle_crop";
+
+/// Writes `pk`, its verifying key and `config` to `dir`, creating it if needed.
+pub fn write_keys(
+    dir: impl AsRef<Path>,
+    pk: &ProvingKey<G1Affine>,
+    config: &CircuitConfig,
+    format: SerdeFormat,
+) -> Result<()> {
+    // store circuit.break_points() after keygen
+    if config.break_points.is_empty() {
+        return Err(ConfigError::MissingBreakPoints.into());
+    }
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let mut writer = BufWriter::new(File::create(dir.join(PK_FILE))?);
+    pk.write(&mut writer, format)?;
+    writer.flush()?;
+
+    let mut writer = BufWriter::new(File::create(dir.join(VK_FILE))?);
+    pk.get_vk().write(&mut writer, format)?;
+    writer.flush()?;
+
+    let writer = BufWriter::new(File::create(dir.join(CONFIG_FILE))?);
+    serde_json::to_writer_pretty(writer, config)?;
+    Ok(())
+}
+
+pub fn read_config(dir: impl AsRef<Path>) -> Result<CircuitConfig> {
+    let reader = BufReader::new(File::open(dir.as_ref().join(CONFIG_FILE))?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+fn check_k(config: &CircuitConfig, vk: &VerifyingKey<G1Affine>) -> Result<()> {
+    Ok(config.check_k(vk.get_domain().k())?)
+}
+
+/// Reads the proving key written by [`write_keys`]. `C` is the circuit type
+/// the key was generated for; the stored config is applied first since
+/// reading a key re-runs `C::configure`.
+pub fn read_pk<C: Circuit<Fr>>(
+    dir: impl AsRef<Path>,
+    format: SerdeFormat,
+) -> Result<(ProvingKey<G1Affine>, CircuitConfig)> {
+    let dir = dir.as_ref();
+    let config = read_config(dir)?;
+    config.apply();
+    let mut reader = BufReader::new(File::open(dir.join(PK_FILE))?);
+    let pk = ProvingKey::read::<_, C>(&mut reader, format)?;
+    check_k(&config, pk.get_vk())?;
+    Ok((pk, config))
+}
+
+/// Reads only the verifying key written by [`write_keys`].
+pub fn read_vk<C: Circuit<Fr>>(
+    dir: impl AsRef<Path>,
+    format: SerdeFormat,
+) -> Result<(VerifyingKey<G1Affine>, CircuitConfig)> {
+    let dir = dir.as_ref();
+    let config = read_config(dir)?;
+    config.apply();
+    let mut reader = BufReader::new(File::open(dir.join(VK_FILE))?);
+    let vk = VerifyingKey::read::<_, C>(&mut reader, format)?;
+    check_k(&config, &vk)?;
+    Ok((vk, config))
+}
+
+/// What a key directory proves, fixed at keygen: crops of a given size out of
+/// images of a given shape, at an offset chosen when proving.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitSpec {
+    pub circuit: CircuitId,
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub bit_depth: usize,
+    pub crop_width: usize,
+    pub crop_height: usize,
+    pub reveal_offset: bool,
+    /// Commits the original as a Merkle tree of bands of this many rows, so
+    /// proving only opens the bands the crop touches instead of hashing the
+    /// whole image.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows_per_leaf: Option<usize>,
+    /// Blinds the original's commitment with a salt from the prover's
+    /// [`Witness`], so the signed commitment cannot be matched against
+    /// guessed images.
+    #[serde(default)]
+    pub hiding: bool,
+}
+
+impl CircuitSpec {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Reads the spec written next to the keys by [`CircuitSpec::write`].
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(dir.as_ref().join(SPEC_FILE))?)?)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(dir.as_ref().join(SPEC_FILE), serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Number of public instances: the two commitments and, if revealed, the
+    /// crop offset.
+    pub fn num_instance(&self) -> usize {
+        if self.reveal_offset {
+            4
+        } else {
+            2
+        }
+    }
+
+    /// The keys only depend on the dimensions, so keygen and sizing run on a
+    /// blank image.
+    pub fn blank(&self) -> Image {
+        vec![0; self.width * self.height * self.channels]
+    }
+
+    pub fn commitment(&self, image: &Image, width: usize, height: usize) -> Result<ImageCommitment> {
+        Ok(ImageCommitment {
+            width,
+            height,
+            channels: self.channels,
+            bit_depth: self.bit_depth,
+            commitment: util::commit_image(image, width, height, self.channels, self.bit_depth)?,
+        })
+    }
+
+    /// The commitment to a full-size image the keys prove crops of: flat, or
+    /// a tiled commitment with one full-width tile per band of
+    /// [`CircuitSpec::rows_per_leaf`] rows, and blinded with `salt` for
+    /// hiding keys.
+    pub fn original_commitment(&self, image: &Image, salt: Option<Fr>) -> Result<ImageCommitment> {
+        let (width, height, channels, bit_depth) = (self.width, self.height, self.channels, self.bit_depth);
+        let mut commitment = match self.rows_per_leaf {
+            Some(rows) => util::commit_tiled_image(image, width, height, channels, bit_depth, width, rows)?,
+            None => util::commit_image(image, width, height, channels, bit_depth)?,
+        };
+        if self.hiding {
+            commitment = util::hiding_commitment(commitment, salt.ok_or(Error::MissingSalt)?);
+        }
+        Ok(ImageCommitment { width, height, channels, bit_depth, commitment })
+    }
+
+    /// Whether `statement` is one the keys can prove: everything in it but the
+    /// commitments and a revealed offset is fixed by the spec, and only the
+    /// commitments and the offset are public instances.
+    pub fn proves(&self, statement: &Statement) -> bool {
+        let shape = |image: &ImageCommitment, width, height| {
+            (image.width, image.height, image.channels, image.bit_depth) == (width, height, self.channels, self.bit_depth)
+        };
+        let crop = match statement.transformation {
+            Transformation::Crop { x, y, width, height } => {
+                x.is_some() == self.reveal_offset
+                    && y.is_some() == self.reveal_offset
+                    && (width, height) == (self.crop_width, self.crop_height)
+            }
+            _ => false,
+        };
+        statement.circuit == self.circuit
+            && crop
+            && shape(&statement.original, self.width, self.height)
+            && shape(&statement.result, self.crop_width, self.crop_height)
+    }
+
+    /// Checks that an image has the shape the keys were generated for.
+    pub fn check_shape(&self, width: usize, height: usize, channels: usize, bit_depth: usize) -> Result<()> {
+        if (width, height, channels, bit_depth) != (self.width, self.height, self.channels, self.bit_depth) {
+            return Err(Error::InvalidDimensions(format!(
+                "keys are for {}x{} images with {} {}-bit channels, got {width}x{height} with {channels} {bit_depth}-bit channels",
+                self.width, self.height, self.channels, self.bit_depth
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Prover-side inputs that stay with the image's owner and never enter a proof
+/// bundle: the salt of a hiding commitment to the original.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Witness {
+    #[serde(with = "hex_fr")]
+    pub salt: Fr,
+}
+
+impl Witness {
+    pub fn random() -> Self {
+        Self { salt: Fr::random(OsRng) }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+}
+
+/// Circuit construction and proving, not part of verify-only builds.
+#[cfg(feature = "prover")]
+impl CircuitSpec {
+    pub fn circuit(
+        &self,
+        stage: CircuitBuilderStage,
+        image: &Image,
+        offset: (usize, usize),
+        salt: Option<Fr>,
+        config: &mut CircuitConfig,
+    ) -> Result<ConfiguredCircuit<Fr>> {
+        let mut builder = GateThreadBuilder::from_stage(stage);
+        let assigned_instances = self.assign(&mut builder, image, offset, salt, &config.params)?;
+        build_circuit(stage, builder, assigned_instances, config)
+    }
+
+    /// Assigns the circuit into `builder` and returns its public instances.
+    /// `salt` is only used by hiding keys, where `None` stands in a zero salt
+    /// for keygen and sizing.
+    pub fn assign(
+        &self,
+        builder: &mut GateThreadBuilder<Fr>,
+        image: &Image,
+        (x, y): (usize, usize),
+        salt: Option<Fr>,
+        params: &CircuitParams,
+    ) -> Result<Vec<AssignedValue<Fr>>> {
+        let range = RangeChip::default(params.lookup_bits);
+        let mut assigned_instances = vec![];
+        if let Some(rows_per_leaf) = self.rows_per_leaf {
+            merkle_crop_and_commit(
+                builder,
+                &range,
+                image,
+                self.width,
+                self.height,
+                self.channels,
+                self.bit_depth,
+                rows_per_leaf,
+                x,
+                y,
+                self.crop_width,
+                self.crop_height,
+                self.reveal_offset,
+                &mut assigned_instances,
+            )?;
+        } else {
+            dynamic_crop_and_commit(
+                builder,
+                &range,
+                image,
+                self.width,
+                self.height,
+                self.channels,
+                self.bit_depth,
+                x,
+                y,
+                self.crop_width,
+                self.crop_height,
+                self.reveal_offset,
+                &mut assigned_instances,
+            )?;
+        }
+        if self.hiding {
+            let ctx = builder.main(0);
+            let salt = ctx.load_witness(salt.unwrap_or(Fr::zero()));
+            assigned_instances[0] = hiding_commitment(ctx, range.gate(), assigned_instances[0], salt);
+        }
+        Ok(assigned_instances)
+    }
+
+    /// Proves the crop at `offset` of `image` and returns the proof bundle
+    /// along with the cropped image. `C` is the circuit type `pk` was read
+    /// with. Hiding keys need `salt`, which stays out of the bundle.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prove<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        image: &Image,
+        (x, y): (usize, usize),
+        salt: Option<Fr>,
+        transcript: Transcript,
+    ) -> Result<(ProofBundle, Image)> {
+        let original = self.original_commitment(image, salt)?;
+        let circuit = self.circuit(CircuitBuilderStage::Prover, image, (x, y), salt, config)?;
+        let instances = circuit.instance();
+        let proof = gen_proof_with(params, pk, C::from(circuit), &instances, transcript)?;
+
+        let (width, height) = (self.crop_width, self.crop_height);
+        let cropped = util::crop_image(image, self.width, self.channels, x, y, width, height)?;
+        let offset = |v| self.reveal_offset.then_some(v);
+        let bundle = ProofBundle::new(
+            self.circuit.clone(),
+            pk.get_vk(),
+            transcript,
+            Transformation::Crop { x: offset(x), y: offset(y), width, height },
+            original,
+            self.commitment(&cropped, width, height)?,
+            instances,
+            proof,
+        );
+        Ok((bundle, cropped))
+    }
+}
+
+#[cfg(all(test, feature = "prover"))]
+mod tests {
+    use super::*;
+    use crate::{
+        circuit::{build_circuit, crop_and_commit},
+        config::CircuitParams,
+    };
+    use halo2_base::{
+        gates::{
+            builder::{CircuitBuilderStage, GateThreadBuilder},
+            RangeChip,
+        },
+        halo2_proofs::{
+            dev::MockProver,
+            halo2curves::bn256::Bn256,
+            plonk::{keygen_pk, keygen_vk},
+            poly::kzg::commitment::ParamsKZG,
+        },
+    };
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn test_keys_roundtrip() {
+        let k = 13;
+        let lookup_bits = 12;
+        let mut config = CircuitConfig::new(CircuitParams::new(k, lookup_bits, Some(9)));
+        config.apply();
+
+        let (width, height, channels, bit_depth) = (16, 8, 3, 8);
+        let image = vec![0; width * height * channels];
+        let mut builder = GateThreadBuilder::<Fr>::keygen();
+        let range = RangeChip::default(lookup_bits);
+        let mut assigned_instances = vec![];
+        crop_and_commit(
+            &mut builder,
+            &range,
+            &image,
+            width,
+            height,
+            channels,
+            bit_depth,
+            2,
+            2,
+            8,
+            4,
+            &mut assigned_instances,
+        )
+        .unwrap();
+        let circuit =
+            build_circuit(CircuitBuilderStage::Keygen, builder, assigned_instances, &mut config).unwrap();
+
+        let params = ParamsKZG::<Bn256>::setup(k as u32, OsRng);
+        let vk = keygen_vk(&params, &circuit).unwrap();
+        let pk = keygen_pk(&params, vk, &circuit).unwrap();
+        config.break_points = circuit.break_points();
+
+        let dir = std::env::temp_dir().join(format!("zkimg-keys-{}", std::process::id()));
+        write_keys(&dir, &pk, &config, SerdeFormat::RawBytes).unwrap();
+        let (vk, loaded) =
+            read_vk::<ConfiguredCircuit<Fr>>(&dir, SerdeFormat::RawBytes).unwrap();
+        let (pk, _) =
+            read_pk::<ConfiguredCircuit<Fr>>(&dir, SerdeFormat::RawBytes).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.break_points, config.break_points);
+        assert_eq!(vk.to_bytes(SerdeFormat::RawBytes), pk.get_vk().to_bytes(SerdeFormat::RawBytes));
+    }
+
+    #[test]
+    fn test_hiding_commitment() {
+        let k = 14;
+        let mut config = CircuitConfig::new(CircuitParams::new(k, k - 1, Some(9)));
+        let spec = CircuitSpec {
+            circuit: CircuitId::new(CROP_CIRCUIT),
+            width: 16,
+            height: 8,
+            channels: 3,
+            bit_depth: 8,
+            crop_width: 9,
+            crop_height: 5,
+            reveal_offset: false,
+            rows_per_leaf: None,
+            hiding: true,
+        };
+        let image = util::generate_image(16, 8, 3, 8).unwrap();
+        let witness = Witness::from_json(&serde_json::to_string(&Witness::random()).unwrap()).unwrap();
+        assert!(matches!(spec.original_commitment(&image, None), Err(Error::MissingSalt)));
+
+        let circuit =
+            spec.circuit(CircuitBuilderStage::Mock, &image, (3, 2), Some(witness.salt), &mut config).unwrap();
+        let instances = circuit.instance();
+        let hidden = spec.original_commitment(&image, Some(witness.salt)).unwrap().commitment;
+        assert_eq!(instances[0], hidden);
+        assert_ne!(hidden, util::commit_image(&image, 16, 8, 3, 8).unwrap());
+        MockProver::run(k as u32, &circuit, vec![instances]).unwrap().assert_satisfied();
+    }
+}
@@ -1,2 +1,26 @@
+#[cfg(feature = "prover")]
+pub mod aggregation;
+pub mod bundle;
+#[cfg(feature = "prover")]
 pub mod circuit;
-pub mod util;
\ No newline at end of file
+pub mod config;
+pub mod error;
+#[cfg(feature = "prover")]
+pub mod estimate;
+#[cfg(feature = "evm")]
+pub mod evm;
+#[cfg(feature = "prover")]
+pub mod history;
+pub mod keys;
+#[cfg(feature = "prover")]
+pub mod prover;
+pub mod ptau;
+pub mod srs;
+#[cfg(feature = "prover")]
+pub mod tile;
+pub mod util;
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::{Error, Result};
\ No newline at end of file
@@ -0,0 +1,86 @@
+use halo2_base::halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::{create_proof, keygen_pk, keygen_vk, Circuit, ProvingKey},
+    poly::kzg::{
+        commitment::{KZGCommitmentScheme, ParamsKZG},
+        multiopen::ProverSHPLONK,
+    },
+    transcript::{Blake2bWrite, Challenge255, EncodedChallenge, TranscriptWriterBuffer},
+};
+use rand::rngs::OsRng;
+#[cfg(feature = "evm")]
+use snark_verifier::system::halo2::transcript::evm::EvmTranscript;
+use snark_verifier::{loader::native::NativeLoader, system::halo2::transcript::halo2::ChallengeScalar};
+
+use crate::{
+    bundle::Transcript,
+    error::{Error, Result},
+    verify::PoseidonTranscript,
+};
+
+/// Runs `keygen_vk` and `keygen_pk` on a circuit built for the keygen stage.
+pub fn keygen<C: Circuit<Fr>>(params: &ParamsKZG<Bn256>, circuit: &C) -> Result<ProvingKey<G1Affine>> {
+    let vk = keygen_vk(params, circuit).map_err(Error::Keygen)?;
+    keygen_pk(params, vk, circuit).map_err(Error::Keygen)
+}
+
+/// Creates a SHPLONK proof for a circuit with a single instance column.
+pub fn gen_proof<C: Circuit<Fr>>(
+    params: &ParamsKZG<Bn256>,
+    pk: &ProvingKey<G1Affine>,
+    circuit: C,
+    instances: &[Fr],
+) -> Result<Vec<u8>> {
+    gen_proof_with(params, pk, circuit, instances, Transcript::Blake2b)
+}
+
+/// [`gen_proof`] with a choice of transcript. Keccak needs the `evm` feature;
+/// Poseidon proofs are the ones that can be verified in a circuit.
+pub fn gen_proof_with<C: Circuit<Fr>>(
+    params: &ParamsKZG<Bn256>,
+    pk: &ProvingKey<G1Affine>,
+    circuit: C,
+    instances: &[Fr],
+    transcript: Transcript,
+) -> Result<Vec<u8>> {
+    match transcript {
+        Transcript::Blake2b => {
+            create::<_, Challenge255<_>, Blake2bWrite<_, G1Affine, _>>(params, pk, circuit, instances)
+        }
+        #[cfg(feature = "evm")]
+        Transcript::Keccak => {
+            create::<_, Challenge255<_>, EvmTranscript<G1Affine, NativeLoader, _, _>>(
+                params, pk, circuit, instances,
+            )
+        }
+        Transcript::Poseidon => {
+            create::<_, ChallengeScalar<_>, PoseidonTranscript<NativeLoader, _>>(params, pk, circuit, instances)
+        }
+        #[allow(unreachable_patterns)]
+        transcript => Err(Error::UnsupportedTranscript(transcript)),
+    }
+}
+
+fn create<C, E, T>(
+    params: &ParamsKZG<Bn256>,
+    pk: &ProvingKey<G1Affine>,
+    circuit: C,
+    instances: &[Fr],
+) -> Result<Vec<u8>>
+where
+    C: Circuit<Fr>,
+    E: EncodedChallenge<G1Affine>,
+    T: TranscriptWriterBuffer<Vec<u8>, G1Affine, E>,
+{
+    let mut transcript = T::init(vec![]);
+    create_proof::<KZGCommitmentScheme<Bn256>, ProverSHPLONK<'_, Bn256>, E, _, T, _>(
+        params,
+        pk,
+        &[circuit],
+        &[&[instances]],
+        OsRng,
+        &mut transcript,
+    )
+    .map_err(Error::Proof)?;
+    Ok(transcript.finalize())
+}
@@ -0,0 +1,350 @@
+//! Conversion of public powers-of-tau ceremony transcripts into the
+//! `kzg_bn254_{k}.srs` files read by [`crate::srs::SrsManager`].
+//!
+//! Two inputs are supported:
+//! - snarkjs `.ptau` files (e.g. the Hermez/Polygon Perpetual Powers of Tau
+//!   downloads), sectioned, coordinates little-endian in Montgomery form;
+//! - Perpetual Powers of Tau `challenge` files, a 64 byte hash followed by
+//!   uncompressed big-endian points.
+
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use halo2_base::halo2_proofs::{
+    arithmetic::{g_to_lagrange, CurveAffine},
+    halo2curves::{
+        bn256::{Bn256, Fq, Fq2, G1Affine, G2Affine, G1},
+        ff::PrimeField,
+        serde::SerdeObject,
+    },
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+};
+use num_bigint::BigUint;
+
+use crate::{
+    error::{Error, Result},
+    srs::{in_g2_subgroup, srs_file_name, validate_srs},
+};
+
+const G1_LEN: usize = 64;
+const G2_LEN: usize = 128;
+const CHALLENGE_HASH_LEN: usize = 64;
+/// BN254 base field modulus, as stored in the `.ptau` header.
+const FQ_MODULUS: &str = "30644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd47";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PtauFormat {
+    Snarkjs,
+    Challenge,
+}
+
+/// The part of a ceremony transcript a KZG setup needs: `[tau^i]_1` for
+/// `i < 2^k`, the G2 generator and `[tau]_2`.
+#[derive(Clone, Debug)]
+pub struct PowersOfTau {
+    /// Largest k the transcript supports.
+    pub power: u32,
+    pub g1: Vec<G1Affine>,
+    pub g2: G2Affine,
+    pub s_g2: G2Affine,
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidParams(msg.into())
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// snarkjs stores coordinates little-endian in Montgomery form with
+/// `R = 2^256`, which is also the raw in-memory layout of halo2curves.
+fn read_fq_montgomery(reader: &mut impl Read) -> Result<Fq> {
+    let mut buf = [0u8; 32];
+    reader.read_exact(&mut buf)?;
+    Fq::from_raw_bytes(&buf).ok_or_else(|| invalid("coordinate is not reduced"))
+}
+
+/// Challenge files store coordinates big-endian in standard form.
+fn read_fq_be(reader: &mut impl Read) -> Result<Fq> {
+    let mut buf = [0u8; 32];
+    reader.read_exact(&mut buf)?;
+    if buf[0] & 0xc0 != 0 {
+        return Err(invalid("compressed or infinity point in challenge file"));
+    }
+    buf.reverse();
+    Option::from(Fq::from_repr(buf)).ok_or_else(|| invalid("coordinate is not reduced"))
+}
+
+fn g1_from_xy(x: Fq, y: Fq) -> Result<G1Affine> {
+    Option::from(G1Affine::from_xy(x, y)).ok_or_else(|| invalid("G1 point is not on the curve"))
+}
+
+fn g2_from_xy(x: Fq2, y: Fq2) -> Result<G2Affine> {
+    let point: G2Affine =
+        Option::from(G2Affine::from_xy(x, y)).ok_or_else(|| invalid("G2 point is not on the curve"))?;
+    if !in_g2_subgroup(point) {
+        return Err(invalid("G2 point is not in the prime order subgroup"));
+    }
+    Ok(point)
+}
+
+/// Reads a snarkjs `.ptau` file, keeping only the first `2^max_k` G1 powers
+/// (all of them if `max_k` is `None` or exceeds the file's power).
+pub fn read_ptau(path: impl AsRef<Path>, max_k: Option<u32>) -> Result<PowersOfTau> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"ptau" {
+        return Err(invalid("missing ptau magic"));
+    }
+    let _version = read_u32(&mut reader)?;
+    let num_sections = read_u32(&mut reader)?;
+
+    // section id -> (offset, size)
+    let mut sections = vec![];
+    for _ in 0..num_sections {
+        let id = read_u32(&mut reader)?;
+        let size = read_u64(&mut reader)?;
+        let offset = reader.stream_position()?;
+        sections.push((id, offset, size));
+        reader.seek(SeekFrom::Current(size as i64))?;
+    }
+    let seek_section = |reader: &mut BufReader<File>, id: u32| -> Result<u64> {
+        let &(_, offset, size) = sections
+            .iter()
+            .find(|(section, _, _)| *section == id)
+            .ok_or_else(|| invalid(format!("missing section {id}")))?;
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(size)
+    };
+
+    // header: n8, q, power, ceremony power
+    seek_section(&mut reader, 1)?;
+    let n8 = read_u32(&mut reader)? as usize;
+    let mut q = vec![0u8; n8];
+    reader.read_exact(&mut q)?;
+    if BigUint::from_bytes_le(&q) != BigUint::parse_bytes(FQ_MODULUS.as_bytes(), 16).unwrap() {
+        return Err(invalid("ptau file is not over BN254"));
+    }
+    let power = read_u32(&mut reader)?;
+    let k = max_k.map_or(power, |max_k| max_k.min(power));
+
+    let size = seek_section(&mut reader, 2)?;
+    if size < ((2u64 << power) - 1) * G1_LEN as u64 {
+        return Err(invalid("tauG1 section is too short"));
+    }
+    let g1 = (0..1usize << k)
+        .map(|_| {
+            let x = read_fq_montgomery(&mut reader)?;
+            let y = read_fq_montgomery(&mut reader)?;
+            g1_from_xy(x, y)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    seek_section(&mut reader, 3)?;
+    let mut read_g2 = || -> Result<G2Affine> {
+        let [x0, x1, y0, y1] = [(); 4].map(|_| read_fq_montgomery(&mut reader));
+        g2_from_xy(Fq2 { c0: x0?, c1: x1? }, Fq2 { c0: y0?, c1: y1? })
+    };
+    let g2 = read_g2()?;
+    let s_g2 = read_g2()?;
+
+    Ok(PowersOfTau { power, g1, g2, s_g2 })
+}
+
+/// Length of an uncompressed Perpetual Powers of Tau challenge file of size
+/// `2^power`: hash, tau G1 (`2^(power+1) - 1`), tau G2, alpha tau G1, beta tau G1
+/// (`2^power` each) and beta G2.
+fn challenge_len(power: u32) -> u64 {
+    let n = 1u64 << power;
+    CHALLENGE_HASH_LEN as u64
+        + (2 * n - 1) * G1_LEN as u64
+        + n * G2_LEN as u64
+        + 2 * n * G1_LEN as u64
+        + G2_LEN as u64
+}
+
+/// Reads a Perpetual Powers of Tau `challenge` file. The file has no header, so
+/// its power is inferred from the length.
+pub fn read_challenge(path: impl AsRef<Path>, max_k: Option<u32>) -> Result<PowersOfTau> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let power = (1..=28)
+        .find(|&power| challenge_len(power) == len)
+        .ok_or_else(|| invalid("file length does not match any challenge size"))?;
+    let k = max_k.map_or(power, |max_k| max_k.min(power));
+    let mut reader = BufReader::new(file);
+
+    reader.seek(SeekFrom::Start(CHALLENGE_HASH_LEN as u64))?;
+    let g1 = (0..1usize << k)
+        .map(|_| {
+            let x = read_fq_be(&mut reader)?;
+            let y = read_fq_be(&mut reader)?;
+            g1_from_xy(x, y)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let g2_offset = CHALLENGE_HASH_LEN as u64 + ((2u64 << power) - 1) * G1_LEN as u64;
+    reader.seek(SeekFrom::Start(g2_offset))?;
+    let mut read_g2 = || -> Result<G2Affine> {
+        // c1 before c0
+        let [x1, x0, y1, y0] = [(); 4].map(|_| read_fq_be(&mut reader));
+        g2_from_xy(Fq2 { c0: x0?, c1: x1? }, Fq2 { c0: y0?, c1: y1? })
+    };
+    let g2 = read_g2()?;
+    let s_g2 = read_g2()?;
+
+    Ok(PowersOfTau { power, g1, g2, s_g2 })
+}
+
+impl PowersOfTau {
+    pub fn read(path: impl AsRef<Path>, format: PtauFormat, max_k: Option<u32>) -> Result<Self> {
+        match format {
+            PtauFormat::Snarkjs => read_ptau(path, max_k),
+            PtauFormat::Challenge => read_challenge(path, max_k),
+        }
+    }
+
+    /// Builds validated KZG parameters for `2^k` rows, computing the Lagrange
+    /// basis commitments from the monomial ones.
+    pub fn to_params(&self, k: u32) -> Result<ParamsKZG<Bn256>> {
+        let n = 1usize << k;
+        if n > self.g1.len() {
+            return Err(invalid(format!("transcript only has {} G1 powers, k = {k} needs {n}", self.g1.len())));
+        }
+        let g = &self.g1[..n];
+        let g_lagrange: Vec<G1Affine> = g_to_lagrange(g.iter().map(|p| G1::from(*p)).collect(), k);
+
+        // assemble the RawBytes layout and let halo2 parse it
+        let mut bytes = Vec::with_capacity(crate::srs::srs_file_len(k) as usize);
+        bytes.extend(k.to_le_bytes());
+        for p in g.iter().chain(&g_lagrange) {
+            p.write_raw(&mut bytes)?;
+        }
+        self.g2.write_raw(&mut bytes)?;
+        self.s_g2.write_raw(&mut bytes)?;
+
+        let params = ParamsKZG::<Bn256>::read(&mut &bytes[..])?;
+        validate_srs(&params)?;
+        Ok(params)
+    }
+}
+
+/// Converts a transcript into one params file per requested k, written to
+/// `out_dir` with the names [`crate::srs::SrsManager`] looks for.
+pub fn convert(
+    input: impl AsRef<Path>,
+    format: PtauFormat,
+    out_dir: impl AsRef<Path>,
+    ks: &[u32],
+) -> Result<Vec<PathBuf>> {
+    let out_dir = out_dir.as_ref();
+    fs::create_dir_all(out_dir)?;
+    let powers = PowersOfTau::read(input, format, ks.iter().max().copied())?;
+
+    ks.iter()
+        .map(|&k| {
+            let params = powers.to_params(k)?;
+            let path = out_dir.join(srs_file_name(k));
+            let mut writer = BufWriter::new(File::create(&path)?);
+            params.write(&mut writer)?;
+            writer.flush()?;
+            log::info!("wrote {path:?}");
+            Ok(path)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::srs::SrsManager;
+    use halo2_base::halo2_proofs::{arithmetic::Field, halo2curves::bn256::Fr};
+
+    /// Writes a toy `.ptau` of size `2^power` for a known tau.
+    fn write_ptau(path: &Path, power: u32, tau: Fr) {
+        let modulus = BigUint::parse_bytes(FQ_MODULUS.as_bytes(), 16).unwrap().to_bytes_le();
+        let mut header = vec![];
+        header.extend(32u32.to_le_bytes());
+        header.extend(modulus);
+        header.extend(power.to_le_bytes());
+        header.extend(power.to_le_bytes());
+
+        let mut tau_g1 = vec![];
+        let mut s = Fr::one();
+        for _ in 0..(2u64 << power) - 1 {
+            G1Affine::from(G1Affine::generator() * s).write_raw(&mut tau_g1).unwrap();
+            s *= tau;
+        }
+        let mut tau_g2 = vec![];
+        let mut s = Fr::one();
+        for _ in 0..1u64 << power {
+            G2Affine::from(G2Affine::generator() * s).write_raw(&mut tau_g2).unwrap();
+            s *= tau;
+        }
+
+        let mut file = vec![];
+        file.extend(b"ptau");
+        file.extend(1u32.to_le_bytes());
+        file.extend(3u32.to_le_bytes());
+        for (id, section) in [(1u32, header), (2, tau_g1), (3, tau_g2)] {
+            file.extend(id.to_le_bytes());
+            file.extend((section.len() as u64).to_le_bytes());
+            file.extend(section);
+        }
+        fs::write(path, file).unwrap();
+    }
+
+    #[test]
+    fn test_convert_ptau() {
+        let dir = std::env::temp_dir().join(format!("zkimg-ptau-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("toy.ptau");
+        let tau = Fr::from(12345);
+        write_ptau(&input, 5, tau);
+
+        let written = convert(&input, PtauFormat::Snarkjs, &dir, &[3, 5]).unwrap();
+        assert_eq!(written.len(), 2);
+
+        let params = SrsManager::new(&dir).load(4).unwrap();
+        assert_eq!(params.k(), 4);
+        assert_eq!(params.get_g()[2], G1Affine::from(G1Affine::generator() * tau.square()));
+
+        // a point moved off the curve is rejected
+        let mut bytes = fs::read(&input).unwrap();
+        let first_g1 = bytes.len() - (1 << 5) * G2_LEN - 12 - ((2 << 5) - 1) * G1_LEN;
+        bytes[first_g1 + G1_LEN + 1] ^= 1;
+        fs::write(&input, bytes).unwrap();
+        assert!(read_ptau(&input, None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_g2_subgroup() {
+        let generator = G2Affine::generator();
+        assert_eq!(g2_from_xy(generator.x, generator.y).unwrap(), generator);
+
+        // on the curve, and almost surely outside the subgroup
+        let (x, y) = (1u64..)
+            .find_map(|x| {
+                let x = Fq2 { c0: Fq::from(x), c1: Fq::zero() };
+                Option::<Fq2>::from((x.square() * x + G2Affine::b()).sqrt()).map(|y| (x, y))
+            })
+            .unwrap();
+        assert!(g2_from_xy(x, y).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, ErrorKind, Read},
+    path::{Path, PathBuf},
+};
+
+use halo2_base::halo2_proofs::{
+    arithmetic::{best_multiexp, CurveAffine, Field},
+    halo2curves::{
+        bn256::{Bn256, Fr, G1Affine, G2Affine, G2},
+        group::{Curve, Group},
+        pairing::Engine,
+    },
+    poly::{
+        commitment::{Blind, Params, ParamsProver},
+        kzg::commitment::ParamsKZG,
+        EvaluationDomain,
+    },
+};
+use rand::rngs::OsRng;
+
+use crate::error::{Error, Result};
+
+/// Size of a `ParamsKZG<Bn256>` file written with `SerdeFormat::RawBytes`: the
+/// k header, `g` and `g_lagrange` (64 bytes per point) and the two G2 points.
+pub fn srs_file_len(k: u32) -> u64 {
+    4 + 2 * (1u64 << k) * 64 + 2 * 128
+}
+
+/// File name used for the SRS of size `2^k`, the same layout as
+/// `halo2_base::utils::fs::gen_srs`.
+pub fn srs_file_name(k: u32) -> String {
+    format!("kzg_bn254_{k}.srs")
+}
+
+/// Loads trusted KZG parameters from a directory of `kzg_bn254_{k}.srs` files.
+///
+/// A locally generated setup knows its own toxic waste, so it is only used
+/// when dev mode is explicitly enabled and never written back to disk.
+#[derive(Clone, Debug)]
+pub struct SrsManager {
+    dir: PathBuf,
+    dev_mode: bool,
+}
+
+impl SrsManager {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), dev_mode: false }
+    }
+
+    /// Allows falling back to `ParamsKZG::setup` when no file for `k` or above
+    /// exists. Only for tests and benchmarks.
+    pub fn dev_mode(mut self, dev_mode: bool) -> Self {
+        self.dev_mode = dev_mode;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Sizes of the parameter files present in the directory, ascending.
+    pub fn available(&self) -> Result<Vec<u32>> {
+        let mut ks = vec![];
+        if !self.dir.exists() {
+            return Ok(ks);
+        }
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let k = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("kzg_bn254_"))
+                .and_then(|name| name.strip_suffix(".srs"))
+                .and_then(|k| k.parse().ok());
+            ks.extend(k);
+        }
+        ks.sort_unstable();
+        Ok(ks)
+    }
+
+    /// Returns parameters for `2^k` rows, downsizing the smallest larger file
+    /// if there is no exact match.
+    pub fn load(&self, k: u32) -> Result<ParamsKZG<Bn256>> {
+        if let Some(&file_k) = self.available()?.iter().find(|&&file_k| file_k >= k) {
+            let mut params = read_srs(self.dir.join(srs_file_name(file_k)))?;
+            if file_k > k {
+                params.downsize(k);
+            }
+            return Ok(params);
+        }
+        if self.dev_mode {
+            log::warn!("no SRS for k = {k} in {:?}, generating an insecure local setup", self.dir);
+            return Ok(ParamsKZG::<Bn256>::setup(k, OsRng));
+        }
+        Err(Error::Io(io::Error::new(
+            ErrorKind::NotFound,
+            format!("no SRS for k = {k} or larger in {:?} and dev mode is disabled", self.dir),
+        )))
+    }
+}
+
+/// Reads a single parameter file and checks it with [`validate_srs`]. The
+/// header must match the file length.
+pub fn read_srs(path: impl AsRef<Path>) -> Result<ParamsKZG<Bn256>> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut header = [0u8; 4];
+    file.read_exact(&mut header)?;
+    let k = u32::from_le_bytes(header);
+    if k == 0 || k > 28 || len != srs_file_len(k) {
+        return Err(Error::InvalidParams(format!("{path:?} is not a RawBytes ParamsKZG<Bn256> file")));
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let params = ParamsKZG::<Bn256>::read(&mut reader)?;
+    validate_srs(&params).map_err(|e| match e {
+        Error::InvalidParams(msg) => Error::InvalidParams(format!("{path:?}: {msg}")),
+        e => e,
+    })?;
+    Ok(params)
+}
+
+/// Unlike G1, the G2 curve has points outside the subgroup of order `r`, so a
+/// point must also satisfy `[r] P == 0`, computed as `[r - 1] P + P`.
+pub(crate) fn in_g2_subgroup(point: G2Affine) -> bool {
+    bool::from((point * -Fr::one() + point).is_identity())
+}
+
+/// Checks that `g[0]` and `g2` are the generators, that `s_g2` is a point of
+/// the G2 subgroup other than the identity, that every `g[i]` is
+/// `s^i * g[0]` for that `s`, and that `g_lagrange` is `g` in the Lagrange
+/// basis. Without the G2 checks, identity G2 points would make every pairing
+/// check, here and in the verifier, hold trivially. The
+/// sequences are compared with random linear combinations: the powers by
+/// `e(Σ r_i * g[i + 1], g2) == e(Σ r_i * g[i], s * g2)`, and `g_lagrange` by
+/// committing to random evaluations both ways.
+pub fn validate_srs(params: &ParamsKZG<Bn256>) -> Result<()> {
+    let g = params.get_g();
+    if g.len() != 1 << params.k() {
+        return Err(Error::InvalidParams(format!(
+            "expected {} G1 points, found {}",
+            1u64 << params.k(),
+            g.len()
+        )));
+    }
+    if g[0] != G1Affine::generator() {
+        return Err(Error::InvalidParams("g[0] is not the G1 generator".to_string()));
+    }
+    if let Some(i) = g.iter().position(|p| !bool::from(p.is_on_curve())) {
+        return Err(Error::InvalidParams(format!("g[{i}] is not on the curve")));
+    }
+    if params.g2() != G2Affine::generator() {
+        return Err(Error::InvalidParams("g2 is not the G2 generator".to_string()));
+    }
+    let s_g2 = params.s_g2();
+    if bool::from(G2::from(s_g2).is_identity()) || !bool::from(s_g2.is_on_curve()) || !in_g2_subgroup(s_g2) {
+        return Err(Error::InvalidParams("s_g2 is not a non-identity point of the G2 subgroup".to_string()));
+    }
+
+    let n = g.len();
+    let r: Vec<Fr> = (0..n).map(|_| Fr::random(OsRng)).collect();
+    let shifted = best_multiexp(&r[..n - 1], &g[1..]).to_affine();
+    let unshifted = best_multiexp(&r[..n - 1], &g[..n - 1]).to_affine();
+    if Bn256::pairing(&shifted, &params.g2()) != Bn256::pairing(&unshifted, &params.s_g2()) {
+        return Err(Error::InvalidParams("G1 points are not powers of the G2 secret".to_string()));
+    }
+
+    let domain = EvaluationDomain::<Fr>::new(1, params.k());
+    let evaluations = domain.lagrange_from_vec(r);
+    let lagrange = params.commit_lagrange(&evaluations, Blind::default());
+    let monomial = params.commit(&domain.lagrange_to_coeff(evaluations), Blind::default());
+    if lagrange.to_affine() != monomial.to_affine() {
+        return Err(Error::InvalidParams("g_lagrange does not match g".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_shipped_srs() {
+        let manager = SrsManager::new("params");
+        assert!(manager.available().unwrap().contains(&8));
+        assert_eq!(manager.load(8).unwrap().k(), 8);
+        // downsized from the k = 8 file
+        assert_eq!(manager.load(6).unwrap().k(), 6);
+        // nothing large enough, and no dev mode
+        assert!(manager.load(9).is_err());
+        assert_eq!(manager.dev_mode(true).load(9).unwrap().k(), 9);
+    }
+
+    #[test]
+    fn test_validate_srs() {
+        let params = ParamsKZG::<Bn256>::setup(4, OsRng);
+        validate_srs(&params).unwrap();
+
+        let mut bytes = vec![];
+        params.write(&mut bytes).unwrap();
+        // swapping two points keeps every point valid and g[0], g[1] intact
+        let swap = |i: usize, j: usize| {
+            let mut bytes = bytes.clone();
+            let (i, j) = (4 + i * 64, 4 + j * 64);
+            let point = bytes[i..i + 64].to_vec();
+            bytes.copy_within(j..j + 64, i);
+            bytes[j..j + 64].copy_from_slice(&point);
+            ParamsKZG::<Bn256>::read(&mut &bytes[..]).unwrap()
+        };
+        // in g, then in g_lagrange
+        assert!(validate_srs(&swap(2, 3)).is_err());
+        assert!(validate_srs(&swap(16 + 2, 16 + 3)).is_err());
+
+        // g2 = s_g2 = identity would make every pairing check trivial
+        let dir = std::env::temp_dir().join(format!("zkimg-srs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(srs_file_name(4));
+        let mut tampered = bytes.clone();
+        let g2_start = tampered.len() - 2 * 128;
+        tampered[g2_start..].fill(0);
+        fs::write(&path, tampered).unwrap();
+        assert!(read_srs(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+//! Tiled proving for images too large for one circuit. The image is split
+//! into equal tiles, each proven on its own with the keys of a [`TileSpec`],
+//! and the tiled circuit of a [`TiledSpec`] verifies every tile proof the way
+//! [`crate::aggregation`] does. It then hashes the tiles' commitments into a
+//! Merkle root and exposes [`crate::util::tiled_commitment`] of the original
+//! and of the edited image, so the one proof links all tiles to the
+//! commitment the original's owner signed.
+//!
+//! Tiles keep their place in the grid, so only per-pixel edits can be tiled.
+//! Tile proofs are made with [`Transcript::Poseidon`], and tiled keys are tied
+//! to one tile key directory and one image size.
+
+use std::{fs, path::Path};
+
+use halo2_base::{
+    gates::{
+        builder::{CircuitBuilderStage, GateThreadBuilder},
+        RangeChip, RangeInstructions,
+    },
+    halo2_proofs::{
+        halo2curves::bn256::{Bn256, Fr, G1Affine},
+        plonk::{Circuit, ProvingKey},
+        poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    },
+    AssignedValue,
+};
+use halo2_ecc::{bn254::FpChip, ecc::BaseFieldEccChip};
+use serde::{Deserialize, Serialize};
+use snark_verifier::{
+    system::halo2::{compile, Config},
+    verifier::plonk::PlonkProtocol,
+};
+
+use crate::{
+    aggregation::{
+        accumulator_limbs, fold, fold_native, succinct_verify, succinct_verify_native, Halo2Loader, Svk,
+    },
+    bundle::{CircuitId, ImageCommitment, TiledBundle, Transformation, Transcript},
+    circuit::{build_circuit, edit_and_commit, tiled_commitment},
+    config::{CircuitConfig, CircuitParams, ConfiguredCircuit},
+    error::{Error, Result},
+    prover::gen_proof_with,
+    util::{self, Image},
+    verify::{ACCUMULATOR_INSTANCES, ACCUMULATOR_LIMBS, ACCUMULATOR_LIMB_BITS},
+};
+
+const TILE_SPEC_FILE: &str = "tile.json";
+const TILED_SPEC_FILE: &str = "tiled.json";
+/// Circuit id of [`TileSpec`].
+pub const TILE_CIRCUIT: &str = "tile_edit";
+/// Circuit id of [`TiledSpec`].
+pub const TILED_CIRCUIT: &str = "tiled_edit";
+
+/// What a tile key directory proves: `edits` applied to one tile of a given
+/// shape. Its public instances are the commitments to the tile and to the
+/// edited tile.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileSpec {
+    pub circuit: CircuitId,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub channels: usize,
+    pub bit_depth: usize,
+    pub edits: Vec<Transformation>,
+}
+
+impl TileSpec {
+    /// Fails if `edits` has a crop or does not apply to tiles of this shape.
+    pub fn new(
+        tile_width: usize,
+        tile_height: usize,
+        channels: usize,
+        bit_depth: usize,
+        edits: Vec<Transformation>,
+    ) -> Result<Self> {
+        let spec = Self { circuit: CircuitId::new(TILE_CIRCUIT), tile_width, tile_height, channels, bit_depth, edits };
+        spec.result_shape()?;
+        Ok(spec)
+    }
+
+    /// Reads the spec written next to the tile keys.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(dir.as_ref().join(TILE_SPEC_FILE))?)?)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(dir.as_ref().join(TILE_SPEC_FILE), serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// `edits` as the circuit and [`util::apply_edits`] take them.
+    pub fn edits(&self) -> Result<Vec<util::Edit>> {
+        let mut bit_depth = self.bit_depth;
+        let mut edits = vec![];
+        for edit in &self.edits {
+            edits.push(match edit {
+                Transformation::FlattenAlpha { background } => {
+                    util::Edit::FlattenAlpha { background: background.clone() }
+                }
+                Transformation::ConvertDepth { from_bits, to_bits } => {
+                    if *from_bits != bit_depth {
+                        return Err(Error::InvalidDimensions(format!(
+                            "conversion from {from_bits} bits of tiles that are {bit_depth}-bit by then"
+                        )));
+                    }
+                    bit_depth = *to_bits;
+                    util::Edit::ConvertDepth { to_bits: *to_bits }
+                }
+                Transformation::Crop { .. } => {
+                    return Err(Error::InvalidDimensions("tiles only take per-pixel edits".to_string()))
+                }
+            });
+        }
+        Ok(edits)
+    }
+
+    /// Channels and bit depth of the edited tiles.
+    pub fn result_shape(&self) -> Result<(usize, usize)> {
+        let (_, _, _, channels, bit_depth) = util::apply_edits(
+            &self.blank(),
+            self.tile_width,
+            self.tile_height,
+            self.channels,
+            self.bit_depth,
+            &self.edits()?,
+        )?;
+        Ok((channels, bit_depth))
+    }
+
+    /// The keys only depend on the tile shape and the edits, so keygen and
+    /// sizing run on a blank tile.
+    pub fn blank(&self) -> Image {
+        vec![0; self.tile_width * self.tile_height * self.channels]
+    }
+
+    pub fn circuit(
+        &self,
+        stage: CircuitBuilderStage,
+        tile: &Image,
+        config: &mut CircuitConfig,
+    ) -> Result<ConfiguredCircuit<Fr>> {
+        let mut builder = GateThreadBuilder::from_stage(stage);
+        let assigned_instances = self.assign(&mut builder, tile, &config.params)?;
+        build_circuit(stage, builder, assigned_instances, config)
+    }
+
+    /// Assigns the circuit into `builder` and returns its public instances.
+    pub fn assign(
+        &self,
+        builder: &mut GateThreadBuilder<Fr>,
+        tile: &Image,
+        params: &CircuitParams,
+    ) -> Result<Vec<AssignedValue<Fr>>> {
+        let range = RangeChip::default(params.lookup_bits);
+        let mut assigned_instances = vec![];
+        edit_and_commit(
+            builder,
+            &range,
+            tile,
+            self.tile_width,
+            self.tile_height,
+            self.channels,
+            self.bit_depth,
+            &self.edits()?,
+            &mut assigned_instances,
+        )?;
+        Ok(assigned_instances)
+    }
+
+    /// Proves the edits of one tile. `params` and `pk` are the tile keys'
+    /// and `C` is the circuit type `pk` was read with.
+    pub fn prove<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        tile: &Image,
+    ) -> Result<TileProof> {
+        let circuit = self.circuit(CircuitBuilderStage::Prover, tile, config)?;
+        let instances = circuit.instance();
+        let proof = gen_proof_with(params, pk, C::from(circuit), &instances, Transcript::Poseidon)?;
+        let protocol = compile(params, pk.get_vk(), Config::kzg().with_num_instance(vec![instances.len()]));
+        Ok(TileProof { protocol, instances, proof })
+    }
+}
+
+/// The proof of one tile, for the tiled circuit to verify.
+#[derive(Clone, Debug)]
+pub struct TileProof {
+    protocol: PlonkProtocol<G1Affine>,
+    instances: Vec<Fr>,
+    proof: Vec<u8>,
+}
+
+/// What a tiled key directory proves: the edits of `tile` on every tile of a
+/// `width` by `height` image.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TiledSpec {
+    pub circuit: CircuitId,
+    pub tile: TileSpec,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl TiledSpec {
+    /// Fails unless the tiles divide the image.
+    pub fn new(tile: TileSpec, width: usize, height: usize) -> Result<Self> {
+        util::tile_grid(width, height, tile.tile_width, tile.tile_height)?;
+        Ok(Self { circuit: CircuitId::new(TILED_CIRCUIT), tile, width, height })
+    }
+
+    /// Reads the spec written next to the tiled keys.
+    pub fn read(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(dir.as_ref().join(TILED_SPEC_FILE))?)?)
+    }
+
+    pub fn write(&self, dir: impl AsRef<Path>) -> Result<()> {
+        Ok(fs::write(dir.as_ref().join(TILED_SPEC_FILE), serde_json::to_string_pretty(self)?)?)
+    }
+
+    pub fn num_tiles(&self) -> usize {
+        (self.width / self.tile.tile_width) * (self.height / self.tile.tile_height)
+    }
+
+    /// The accumulator limbs followed by the original and result commitments.
+    pub fn num_instance(&self) -> usize {
+        ACCUMULATOR_INSTANCES + 2
+    }
+
+    /// Whether `bundle` claims what these keys prove: this circuit, tile size
+    /// and edits, on images of this shape.
+    pub fn proves(&self, bundle: &TiledBundle) -> bool {
+        let tile = &self.tile;
+        let shape = |image: &ImageCommitment, channels, bit_depth| {
+            (image.width, image.height) == (self.width, self.height)
+                && (image.channels, image.bit_depth) == (channels, bit_depth)
+        };
+        bundle.circuit == self.circuit
+            && (bundle.tile_width, bundle.tile_height) == (tile.tile_width, tile.tile_height)
+            && bundle.edits == tile.edits
+            && shape(&bundle.original, tile.channels, tile.bit_depth)
+            && matches!(tile.result_shape(), Ok((channels, bit_depth)) if shape(&bundle.result, channels, bit_depth))
+    }
+
+    /// The tiled commitment to an image of this size given the commitments of
+    /// its tiles.
+    pub fn commitment(&self, leaves: &[Fr], channels: usize, bit_depth: usize) -> ImageCommitment {
+        let (tile_width, tile_height) = (self.tile.tile_width, self.tile.tile_height);
+        ImageCommitment {
+            width: self.width,
+            height: self.height,
+            channels,
+            bit_depth,
+            commitment: util::tiled_commitment(
+                self.width,
+                self.height,
+                channels,
+                bit_depth,
+                tile_width,
+                tile_height,
+                leaves,
+            ),
+        }
+    }
+
+    /// Splits `image` and proves every tile with the tile keys, in the
+    /// row-major order the tiled circuit expects.
+    pub fn prove_tiles<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        image: &Image,
+    ) -> Result<Vec<TileProof>> {
+        let tile = &self.tile;
+        util::check_image(image, self.width, self.height, tile.channels, tile.bit_depth)?;
+        util::split_tiles(image, self.width, self.height, tile.channels, tile.tile_width, tile.tile_height)?
+            .iter()
+            .map(|image| tile.prove::<C>(params, pk, config, image))
+            .collect()
+    }
+
+    /// Keygen runs on a proof of the blank tile for every tile.
+    pub fn blank_proofs(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+    ) -> Result<Vec<TileProof>> {
+        let blank = self.tile.prove::<ConfiguredCircuit<Fr>>(params, pk, config, &self.tile.blank())?;
+        Ok(vec![blank; self.num_tiles()])
+    }
+
+    pub fn circuit(
+        &self,
+        stage: CircuitBuilderStage,
+        params: &ParamsKZG<Bn256>,
+        proofs: &[TileProof],
+        config: &mut CircuitConfig,
+    ) -> Result<ConfiguredCircuit<Fr>> {
+        if proofs.len() != self.num_tiles() {
+            return Err(Error::Aggregation(format!(
+                "keys are for {} tiles, got {} tile proofs",
+                self.num_tiles(),
+                proofs.len()
+            )));
+        }
+        let svk: Svk = params.get_g()[0].into();
+        let mut accumulators = vec![];
+        for proof in proofs {
+            accumulators.extend(succinct_verify_native(&svk, &proof.protocol, &proof.instances, &proof.proof)?);
+        }
+        let as_proof = fold_native(&accumulators)?;
+
+        let range = RangeChip::<Fr>::default(config.params.lookup_bits);
+        let fp_chip = FpChip::<Fr>::new(&range, ACCUMULATOR_LIMB_BITS, ACCUMULATOR_LIMBS);
+        let loader = Halo2Loader::new(BaseFieldEccChip::new(&fp_chip), GateThreadBuilder::from_stage(stage));
+
+        let mut accumulators = vec![];
+        let (mut originals, mut results) = (vec![], vec![]);
+        for proof in proofs {
+            let protocol = proof.protocol.loaded(&loader);
+            let (instances, accumulator) =
+                succinct_verify(&svk, &loader, &protocol, &proof.instances, &proof.proof)?;
+            accumulators.extend(accumulator);
+            originals.push(instances[0]);
+            results.push(instances[1]);
+        }
+        let accumulator = fold(&loader, &accumulators, &as_proof)?;
+        let mut assigned_instances = accumulator_limbs(accumulator);
+
+        let mut builder = loader.take_ctx();
+        let ctx = builder.main(0);
+        let tile = &self.tile;
+        let (channels, bit_depth) = tile.result_shape()?;
+        for (leaves, channels, bit_depth) in
+            [(originals, tile.channels, tile.bit_depth), (results, channels, bit_depth)]
+        {
+            assigned_instances.push(tiled_commitment(
+                ctx,
+                range.gate(),
+                &leaves,
+                self.width,
+                self.height,
+                channels,
+                bit_depth,
+                tile.tile_width,
+                tile.tile_height,
+            ));
+        }
+        build_circuit(stage, builder, assigned_instances, config)
+    }
+
+    /// Proves the tiled edit from the proofs of [`TiledSpec::prove_tiles`]
+    /// and returns its bundle. `C` is the circuit type `pk` was read with.
+    pub fn prove<C: Circuit<Fr> + From<ConfiguredCircuit<Fr>>>(
+        &self,
+        params: &ParamsKZG<Bn256>,
+        pk: &ProvingKey<G1Affine>,
+        config: &mut CircuitConfig,
+        proofs: &[TileProof],
+        transcript: Transcript,
+    ) -> Result<TiledBundle> {
+        let circuit = self.circuit(CircuitBuilderStage::Prover, params, proofs, config)?;
+        let instances = circuit.instance();
+        let proof = gen_proof_with(params, pk, C::from(circuit), &instances, transcript)?;
+
+        let leaves = |row: usize| proofs.iter().map(|proof| proof.instances[row]).collect::<Vec<_>>();
+        let (channels, bit_depth) = self.tile.result_shape()?;
+        Ok(TiledBundle::new(
+            self.circuit.clone(),
+            pk.get_vk(),
+            transcript,
+            (self.tile.tile_width, self.tile.tile_height),
+            self.tile.edits.clone(),
+            self.commitment(&leaves(0), self.tile.channels, self.tile.bit_depth),
+            self.commitment(&leaves(1), channels, bit_depth),
+            instances,
+            proof,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        prover::keygen,
+        srs::SrsManager,
+        verify::{verify_tiled, VerifyError},
+    };
+
+    #[test]
+    fn test_tiled_edit() {
+        let (tile_k, k) = (13, 22);
+        let (width, height, channels, bit_depth) = (16, 8, 4, 8);
+        let edits = vec![Transformation::FlattenAlpha { background: vec![255, 255, 255] }];
+        let tile = TileSpec::new(8, 4, channels, bit_depth, edits).unwrap();
+        let spec = TiledSpec::new(tile.clone(), width, height).unwrap();
+        assert_eq!(spec.num_tiles(), 4);
+        // both key sets must come from the same setup
+        let params = SrsManager::new("params").dev_mode(true).load(k as u32).unwrap();
+        let mut tile_params = params.clone();
+        tile_params.downsize(tile_k as u32);
+
+        let mut tile_config = CircuitConfig::new(CircuitParams::new(tile_k, 12, Some(9)));
+        let circuit = tile.circuit(CircuitBuilderStage::Keygen, &tile.blank(), &mut tile_config).unwrap();
+        let tile_pk = keygen(&tile_params, &circuit).unwrap();
+        tile_config.break_points = circuit.break_points();
+
+        let blank = spec.blank_proofs(&tile_params, &tile_pk, &mut tile_config).unwrap();
+        let mut config = CircuitConfig::new(CircuitParams::new(k, k - 1, Some(20)));
+        let circuit = spec.circuit(CircuitBuilderStage::Keygen, &params, &blank, &mut config).unwrap();
+        let pk = keygen(&params, &circuit).unwrap();
+        config.break_points = circuit.break_points();
+
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let proofs = spec
+            .prove_tiles::<ConfiguredCircuit<Fr>>(&tile_params, &tile_pk, &mut tile_config, &image)
+            .unwrap();
+        let bundle = spec
+            .prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &proofs, Transcript::Blake2b)
+            .unwrap();
+        assert_eq!(bundle.instances.len(), spec.num_instance());
+        verify_tiled(&params, pk.get_vk(), &spec, &bundle).unwrap();
+
+        // the commitments are the ones anyone with the pixels computes
+        let (edited, _, _, edited_channels, edited_depth) =
+            util::apply_edits(&image, width, height, channels, bit_depth, &tile.edits().unwrap()).unwrap();
+        assert_eq!(
+            bundle.original.commitment,
+            util::commit_tiled_image(&image, width, height, channels, bit_depth, 8, 4).unwrap()
+        );
+        assert_eq!(
+            bundle.result.commitment,
+            util::commit_tiled_image(&edited, width, height, edited_channels, edited_depth, 8, 4).unwrap()
+        );
+
+        let mut forged = bundle.clone();
+        forged.result.commitment = forged.original.commitment;
+        assert!(matches!(
+            verify_tiled(&params, pk.get_vk(), &spec, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+        // the edits, tile size and shapes are those of the keys
+        let mut forged = bundle.clone();
+        forged.edits = vec![Transformation::ConvertDepth { from_bits: 8, to_bits: 4 }];
+        assert!(matches!(
+            verify_tiled(&params, pk.get_vk(), &spec, &forged),
+            Err(VerifyError::StatementMismatch)
+        ));
+        let mut forged = bundle.clone();
+        (forged.tile_width, forged.tile_height) = (4, 8);
+        assert!(matches!(
+            verify_tiled(&params, pk.get_vk(), &spec, &forged),
+            Err(VerifyError::StatementMismatch)
+        ));
+        let mut forged = bundle.clone();
+        forged.result.channels = 4;
+        assert!(matches!(
+            verify_tiled(&params, pk.get_vk(), &spec, &forged),
+            Err(VerifyError::StatementMismatch)
+        ));
+
+        // every tile must be proven
+        assert!(matches!(
+            spec.circuit(CircuitBuilderStage::Prover, &params, &proofs[1..], &mut config),
+            Err(Error::Aggregation(_))
+        ));
+        let crop = Transformation::Crop { x: Some(0), y: Some(0), width: 4, height: 4 };
+        assert!(TileSpec::new(8, 4, channels, bit_depth, vec![crop]).is_err());
+    }
+}
@@ -1,15 +1,23 @@
 
 use halo2_base::halo2_proofs::{
     arithmetic::CurveAffine,
-    halo2curves::secp256k1::{Fp, Fq, Secp256k1Affine},
+    halo2curves::{
+        bn256::Fr,
+        ff::PrimeField,
+        secp256k1::{Fp, Fq, Secp256k1Affine},
+    },
 };
-use halo2_base::utils::fe_to_biguint;
+use halo2_base::utils::{fe_to_biguint, ScalarField};
 use num_bigint::BigUint;
 use rand::{rngs::StdRng, SeedableRng};
 use rand::Rng;
 use num_integer::Integer;
 use halo2_base::halo2_proofs::arithmetic::Field;
-type Image = Vec<u64>; 
+use serde::{Deserialize, Serialize};
+use snark_verifier::{loader::native::NativeLoader, util::hash::Poseidon};
+
+use crate::error::{Error, Result};
+pub type Image = Vec<u64>;
 
 #[derive(Clone, Copy, Debug)]
 pub struct SchnorrInput {
@@ -45,34 +53,362 @@ pub fn random_schnorr_signature_input() -> SchnorrInput {
     SchnorrInput{ r, s, msg_hash, pk }
 }
 
+// 署名するメッセージ: 画像のコミットメント (BN254 の Fr) を secp256k1 のスカラーとして読む
+// Fr の位数は secp256k1 の位数より小さいので値はそのまま
+pub fn commitment_message(commitment: Fr) -> Fq {
+    Fq::from_repr(commitment.to_repr()).unwrap()
+}
+
+// 署名の検証 (回路と同じ条件): R = s⋅G - e⋅P が無限遠点でなく、R.y が偶数で R.x == r
+pub fn verify_schnorr_signature(input: &SchnorrInput) -> Result<()> {
+    if bool::from(input.s.is_zero()) || bool::from(input.msg_hash.is_zero()) {
+        return Err(Error::InvalidSignature);
+    }
+    let r_point = Secp256k1Affine::from(Secp256k1Affine::generator() * input.s - input.pk * input.msg_hash);
+    let coordinates = Option::from(r_point.coordinates()).ok_or(Error::InvalidSignature)?;
+    let y_is_even = fe_to_biguint(coordinates.y()).mod_floor(&BigUint::from(2u64)) == BigUint::from(0u64);
+    if !y_is_even || *coordinates.x() != input.r {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(())
+}
+
+// 元画像のコミットメントへの署名 (JSON)。値は 32 バイトのリトルエンディアン hex で、証明バンドルと同じ
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Signature {
+    pub r: String,
+    pub s: String,
+    pub pk_x: String,
+    pub pk_y: String,
+}
+
+fn parse_repr<F: PrimeField<Repr = [u8; 32]>>(name: &str, value: &str) -> Result<F> {
+    let bytes = hex::decode(value.trim_start_matches("0x")).map_err(|e| Error::Encoding(format!("{name}: {e}")))?;
+    let repr: [u8; 32] = bytes.try_into().map_err(|_| Error::Encoding(format!("{name} must be 32 bytes")))?;
+    Option::from(F::from_repr(repr)).ok_or_else(|| Error::Encoding(format!("{name} is not reduced")))
+}
+
+impl Signature {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    // commitment_message(commitment) への署名かどうか
+    pub fn verify(&self, commitment: Fr) -> Result<()> {
+        let pk = Secp256k1Affine::from_xy(parse_repr::<Fp>("pk_x", &self.pk_x)?, parse_repr::<Fp>("pk_y", &self.pk_y)?);
+        let input = SchnorrInput {
+            r: parse_repr::<Fp>("r", &self.r)?,
+            s: parse_repr::<Fq>("s", &self.s)?,
+            msg_hash: commitment_message(commitment),
+            pk: Option::from(pk).ok_or(Error::InvalidSignature)?,
+        };
+        verify_schnorr_signature(&input)
+    }
+}
+
 
-// ランダムなRGB画像を生成
-pub fn generate_image(width: usize, height: usize) -> Image {
+// 1チャンネルあたりの最大ビット深度 (RAW現像前の12〜16bitを想定)
+pub const MAX_BIT_DEPTH: usize = 16;
+
+pub fn check_bit_depth(bit_depth: usize) -> Result<()> {
+    if bit_depth < 1 || bit_depth > MAX_BIT_DEPTH {
+        return Err(Error::UnsupportedBitDepth(bit_depth));
+    }
+    Ok(())
+}
+
+// bit_depth ビットのピクセル値の最大値 (bit_depth は check_bit_depth 済みであること)
+pub fn max_value(bit_depth: usize) -> u64 {
+    debug_assert!(bit_depth >= 1 && bit_depth <= MAX_BIT_DEPTH, "unsupported bit depth {bit_depth}");
+    (1 << bit_depth) - 1
+}
+
+// 画像のサイズとピクセル値の範囲をチェック
+pub fn check_image(image: &[u64], width: usize, height: usize, channels: usize, bit_depth: usize) -> Result<()> {
+    check_bit_depth(bit_depth)?;
+    if width == 0 || height == 0 || channels == 0 {
+        return Err(Error::InvalidDimensions(format!("empty image {width}x{height}x{channels}")));
+    }
+    if image.len() != width * height * channels {
+        return Err(Error::InvalidDimensions(format!(
+            "{width}x{height}x{channels} image needs {} values, got {}",
+            width * height * channels,
+            image.len()
+        )));
+    }
+    let max = max_value(bit_depth);
+    if let Some((index, &value)) = image.iter().enumerate().find(|(_, &v)| v > max) {
+        return Err(Error::PixelOutOfRange { index, value, bit_depth });
+    }
+    Ok(())
+}
+
+// クロップ範囲が画像の中に収まっているかチェック
+pub fn check_crop(width: usize, height: usize, x: usize, y: usize, crop_width: usize, crop_height: usize) -> Result<()> {
+    if crop_width == 0 || crop_height == 0 {
+        return Err(Error::InvalidDimensions(format!("empty crop {crop_width}x{crop_height}")));
+    }
+    if x + crop_width > width || y + crop_height > height {
+        return Err(Error::InvalidDimensions(format!(
+            "crop {crop_width}x{crop_height} at ({x}, {y}) exceeds the {width}x{height} image"
+        )));
+    }
+    Ok(())
+}
+
+// ランダムな画像を生成 (channels = 3 ならRGB, 4 ならRGBA, 各値は bit_depth ビット)
+pub fn generate_image(width: usize, height: usize, channels: usize, bit_depth: usize) -> Result<Image> {
+    check_bit_depth(bit_depth)?;
     let mut rng = rand::thread_rng();
-    let mut image = vec![0; width * height * 3];
-    for i in 0..(width * height * 3) {
-        image[i] = rng.gen_range(0..=255);
+    let max = max_value(bit_depth);
+    let mut image = vec![0; width * height * channels];
+    for i in 0..(width * height * channels) {
+        image[i] = rng.gen_range(0..=max);
     }
-    image
+    Ok(image)
 }
 
-// クロップ関数
-pub fn crop_image(image: &Image, width: usize, x: usize, y: usize, crop_width: usize, crop_height: usize) -> Image {
-    let mut cropped_image = vec![0; crop_width * crop_height * 3];
+// クロップ関数 (高さは image の長さから求める)
+pub fn crop_image(image: &Image, width: usize, channels: usize, x: usize, y: usize, crop_width: usize, crop_height: usize) -> Result<Image> {
+    if width == 0 || channels == 0 || image.len() % (width * channels) != 0 {
+        return Err(Error::InvalidDimensions(format!(
+            "{} values is not a whole number of {width}x{channels} rows",
+            image.len()
+        )));
+    }
+    check_crop(width, image.len() / (width * channels), x, y, crop_width, crop_height)?;
+    let mut cropped_image = vec![0; crop_width * crop_height * channels];
 
     for new_y in 0..crop_height {
         for new_x in 0..crop_width {
             let old_x = x + new_x;
             let old_y = y + new_y;
 
-            let old_index = (old_y * width + old_x) * 3;
-            let new_index = (new_y * crop_width + new_x) * 3;
+            let old_index = (old_y * width + old_x) * channels;
+            let new_index = (new_y * crop_width + new_x) * channels;
 
-            cropped_image[new_index..new_index + 3].copy_from_slice(&image[old_index..old_index + 3]);
+            cropped_image[new_index..new_index + channels].copy_from_slice(&image[old_index..old_index + channels]);
         }
     }
 
-    cropped_image
+    Ok(cropped_image)
+}
+
+// アルファ合成: 最後のチャンネルをアルファとして背景色の上に合成し、アルファチャンネルを落とす
+// out = (c * a + bg * (max - a) + max / 2) / max
+pub fn flatten_alpha(image: &Image, channels: usize, bit_depth: usize, background: &[u64]) -> Result<Image> {
+    check_bit_depth(bit_depth)?;
+    if channels < 2 || background.len() + 1 != channels || image.len() % channels != 0 {
+        return Err(Error::InvalidDimensions(format!(
+            "{channels} channels with alpha need {} background values and whole pixels",
+            channels.saturating_sub(1)
+        )));
+    }
+    let max = max_value(bit_depth);
+    if let Some((index, &value)) = image.iter().chain(background).enumerate().find(|(_, &v)| v > max) {
+        return Err(Error::PixelOutOfRange { index, value, bit_depth });
+    }
+    let colour_channels = channels - 1;
+    let mut flattened = Vec::with_capacity(image.len() / channels * colour_channels);
+
+    for pixel in image.chunks(channels) {
+        let alpha = pixel[colour_channels];
+        for (c, bg) in pixel[..colour_channels].iter().zip(background) {
+            flattened.push((c * alpha + bg * (max - alpha) + max / 2) / max);
+        }
+    }
+
+    Ok(flattened)
+}
+
+// ビット深度の変換 (例: 16bit のマスターから 8bit への書き出し)
+// out = (v * max_to + max_from / 2) / max_from
+pub fn convert_depth(image: &Image, from_bits: usize, to_bits: usize) -> Result<Image> {
+    check_bit_depth(from_bits)?;
+    check_bit_depth(to_bits)?;
+    let max_from = max_value(from_bits);
+    let max_to = max_value(to_bits);
+    if let Some((index, &value)) = image.iter().enumerate().find(|(_, &v)| v > max_from) {
+        return Err(Error::PixelOutOfRange { index, value, bit_depth: from_bits });
+    }
+    Ok(image.iter().map(|v| (v * max_to + max_from / 2) / max_from).collect())
+}
+
+// 画像の編集 (回路の edit_and_commit と同じ順序で適用する)
+// クロップは x, y を含めて回路に固定される
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Crop { x: usize, y: usize, width: usize, height: usize },
+    FlattenAlpha { background: Vec<u64> },
+    ConvertDepth { to_bits: usize },
+}
+
+// 画像とそのサイズ (width, height, channels, bit_depth)
+pub type Shaped = (Image, usize, usize, usize, usize);
+
+// 編集を順に適用する
+pub fn apply_edits(image: &Image, width: usize, height: usize, channels: usize, bit_depth: usize, edits: &[Edit]) -> Result<Shaped> {
+    check_image(image, width, height, channels, bit_depth)?;
+    let mut shaped = (image.clone(), width, height, channels, bit_depth);
+    for edit in edits {
+        let (image, width, height, channels, bit_depth) = shaped;
+        shaped = match edit {
+            Edit::Crop { x, y, width: w, height: h } => {
+                (crop_image(&image, width, channels, *x, *y, *w, *h)?, *w, *h, channels, bit_depth)
+            }
+            Edit::FlattenAlpha { background } => {
+                (flatten_alpha(&image, channels, bit_depth, background)?, width, height, channels - 1, bit_depth)
+            }
+            Edit::ConvertDepth { to_bits } => {
+                (convert_depth(&image, bit_depth, *to_bits)?, width, height, channels, *to_bits)
+            }
+        };
+    }
+    Ok(shaped)
+}
+
+// 全てのクロップを合成した範囲 (x, y, width, height)
+// クロップはピクセルごとの編集と順序を入れ替えられるので、まとめて最初に行える
+pub fn crop_window(width: usize, height: usize, edits: &[Edit]) -> Result<(usize, usize, usize, usize)> {
+    let mut window = (0, 0, width, height);
+    for edit in edits {
+        if let Edit::Crop { x, y, width: w, height: h } = *edit {
+            check_crop(window.2, window.3, x, y, w, h)?;
+            window = (window.0 + x, window.1 + y, w, h);
+        }
+    }
+    Ok(window)
+}
+
+// 1つの BN254 スカラーに安全に詰められるビット数 (Fr は 254bit なので 253bit まで)
+pub const PACKED_BITS: usize = 253;
+
+// 1つのフィールド要素に詰められるピクセル値の数 (8bit なら 31, 16bit なら 15)
+pub fn pack_capacity(bit_depth: usize) -> usize {
+    PACKED_BITS / bit_depth
+}
+
+// 1行を詰めたときのフィールド要素の数
+pub fn packed_row_len(width: usize, channels: usize, bit_depth: usize) -> usize {
+    let capacity = pack_capacity(bit_depth);
+    (width * channels + capacity - 1) / capacity
+}
+
+// ピクセル値を little-endian で詰める: values[i] は 2^(i * bit_depth) の位置に入る
+// (値が bit_depth ビットに収まることは呼び出し側で check_image 済みであること)
+pub fn pack_pixels<F: ScalarField>(values: &[u64], bit_depth: usize) -> Vec<F> {
+    let shift = F::from(1 << bit_depth);
+    values
+        .chunks(pack_capacity(bit_depth))
+        .map(|chunk| chunk.iter().rev().fold(F::zero(), |acc, &v| acc * shift + F::from(v)))
+        .collect()
+}
+
+// 画像を行ごとに詰める (行の境界はまたがないので、クロップは行単位で扱える)
+pub fn pack_image<F: ScalarField>(image: &Image, width: usize, channels: usize, bit_depth: usize) -> Vec<F> {
+    image.chunks(width * channels).flat_map(|row| pack_pixels(row, bit_depth)).collect()
+}
+
+// Poseidon のパラメータ (BN254, t = 3)
+pub const POSEIDON_T: usize = 3;
+pub const POSEIDON_RATE: usize = 2;
+pub const POSEIDON_R_F: usize = 8;
+pub const POSEIDON_R_P: usize = 57;
+
+pub fn poseidon_hash<F: ScalarField>(inputs: &[F]) -> F {
+    let mut hasher = Poseidon::<F, F, POSEIDON_T, POSEIDON_RATE>::new(&NativeLoader, POSEIDON_R_F, POSEIDON_R_P);
+    hasher.update(inputs);
+    hasher.squeeze()
+}
+
+// 画像のコミットメント: Poseidon(width, height, channels, bit_depth, 行ごとに詰めた値...)
+// サイズも含めるので、同じ値の並びでも形の違う画像は別のコミットメントになる
+pub fn commit_image<F: ScalarField>(image: &Image, width: usize, height: usize, channels: usize, bit_depth: usize) -> Result<F> {
+    check_image(image, width, height, channels, bit_depth)?;
+    let header = [width, height, channels, bit_depth].map(|x| F::from(x as u64));
+    let packed = pack_image::<F>(image, width, channels, bit_depth);
+    Ok(poseidon_hash(&[&header[..], &packed[..]].concat()))
+}
+
+// タイルの数 (横, 縦)
+// 端のタイルも同じ形にして 1 つの鍵で証明できるよう、タイルの大きさは画像の大きさを割り切ること
+pub fn tile_grid(width: usize, height: usize, tile_width: usize, tile_height: usize) -> Result<(usize, usize)> {
+    if tile_width == 0 || tile_height == 0 || width % tile_width != 0 || height % tile_height != 0 {
+        return Err(Error::InvalidDimensions(format!(
+            "{tile_width}x{tile_height} tiles do not divide a {width}x{height} image"
+        )));
+    }
+    Ok((width / tile_width, height / tile_height))
+}
+
+// 画像をタイルに分割する (左上から行ごとの順)
+pub fn split_tiles(image: &Image, width: usize, height: usize, channels: usize, tile_width: usize, tile_height: usize) -> Result<Vec<Image>> {
+    let (columns, rows) = tile_grid(width, height, tile_width, tile_height)?;
+    (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column * tile_width, row * tile_height)))
+        .map(|(x, y)| crop_image(image, width, channels, x, y, tile_width, tile_height))
+        .collect()
+}
+
+// 隠蔽コミットメント: Poseidon(コミットメント, salt)。salt を知らなければ候補の画像を総当たりしても一致を確かめられない
+// salt は証明者だけが持ち、証明バンドルには入れない
+pub fn hiding_commitment<F: ScalarField>(commitment: F, salt: F) -> F {
+    poseidon_hash(&[commitment, salt])
+}
+
+// Poseidon の二分木の各段 (葉の段から根の段まで)
+// 葉の数が 2 のべきになるまで 0 で埋める
+pub fn merkle_tree<F: ScalarField>(leaves: &[F]) -> Vec<Vec<F>> {
+    let mut layer = leaves.to_vec();
+    layer.resize(leaves.len().next_power_of_two(), F::zero());
+    let mut tree = vec![layer];
+    while tree[tree.len() - 1].len() > 1 {
+        let next = tree[tree.len() - 1].chunks(2).map(poseidon_hash).collect();
+        tree.push(next);
+    }
+    tree
+}
+
+pub fn merkle_root<F: ScalarField>(leaves: &[F]) -> F {
+    merkle_tree(leaves).pop().unwrap()[0]
+}
+
+// index 番目の葉を開く経路: 葉に近い段から順に、たどるノードの兄弟
+pub fn merkle_path<F: ScalarField>(tree: &[Vec<F>], index: usize) -> Vec<F> {
+    tree[..tree.len() - 1].iter().enumerate().map(|(level, layer)| layer[(index >> level) ^ 1]).collect()
+}
+
+// 経路から根を計算する (index のその段のビットが 1 ならたどるノードは右の子)
+pub fn merkle_root_from_path<F: ScalarField>(leaf: F, index: usize, path: &[F]) -> F {
+    path.iter().enumerate().fold(leaf, |node, (level, &sibling)| {
+        if (index >> level) & 1 == 0 {
+            poseidon_hash(&[node, sibling])
+        } else {
+            poseidon_hash(&[sibling, node])
+        }
+    })
+}
+
+// タイル分割した画像のコミットメント:
+// Poseidon(width, height, channels, bit_depth, tile_width, tile_height, 各タイルの commit_image の木の根)
+// タイルのコミットメントは互いに独立なので、タイルごとに別の証明で扱える
+// 横幅いっぱいのタイルなら行ごとの木になり、クロップは触れる行のタイルだけを開けばよい
+pub fn commit_merkle_root<F: ScalarField>(width: usize, height: usize, channels: usize, bit_depth: usize, tile_width: usize, tile_height: usize, root: F) -> F {
+    let header = [width, height, channels, bit_depth, tile_width, tile_height].map(|x| F::from(x as u64));
+    poseidon_hash(&[&header[..], &[root]].concat())
+}
+
+pub fn tiled_commitment<F: ScalarField>(width: usize, height: usize, channels: usize, bit_depth: usize, tile_width: usize, tile_height: usize, leaves: &[F]) -> F {
+    commit_merkle_root(width, height, channels, bit_depth, tile_width, tile_height, merkle_root(leaves))
+}
+
+pub fn commit_tiled_image<F: ScalarField>(image: &Image, width: usize, height: usize, channels: usize, bit_depth: usize, tile_width: usize, tile_height: usize) -> Result<F> {
+    check_image(image, width, height, channels, bit_depth)?;
+    let leaves = split_tiles(image, width, height, channels, tile_width, tile_height)?
+        .iter()
+        .map(|tile| commit_image(tile, tile_width, tile_height, channels, bit_depth))
+        .collect::<Result<Vec<F>>>()?;
+    Ok(tiled_commitment(width, height, channels, bit_depth, tile_width, tile_height, &leaves))
 }
 
 #[cfg(test)]
@@ -83,43 +419,168 @@ mod tests {
     fn test_generate_image() {
         let width = 10;
         let height = 10;
-        let image = generate_image(width, height);
+        let image = generate_image(width, height, 3, 8).unwrap();
 
         // 画像のサイズが正しいか確認（width * height * 3 ）
         assert_eq!(image.len(), width * height * 3);
+
+        let image = generate_image(width, height, 4, 16).unwrap();
+        assert!(image.iter().all(|&v| v <= 65535));
+        assert_eq!(image.len(), width * height * 4);
     }
 
     #[test]
     fn test_crop_image() {
-        let width = 10;
-        let height = 10;
-        let image = generate_image(width, height);
-
-        let crop_x = 2;
-        let crop_y = 2;
-        let crop_width = 5;
-        let crop_height = 5;
-        let cropped_image = crop_image(&image, width, crop_x, crop_y, crop_width, crop_height);
-
-        // クロップされた画像のサイズが正しいか確認（crop_width * crop_height * 3）
-        assert_eq!(cropped_image.len(), crop_width * crop_height * 3);
-
-        // クロップされた画像が元の画像と一致しているか確認
-        for y in 0..crop_height {
-            for x in 0..crop_width {
-                for rgb in 0..3 {
-                    let old_x = crop_x + x;
-                    let old_y = crop_y + y;
-
-                    let old_index = (old_y * width + old_x) * 3;
-                    let new_index = (y * crop_width + x) * 3;
-    
-                    let old_pixel = &image[old_index + rgb];
-                    let new_pixel = &cropped_image[new_index + rgb];
-    
-                    assert_eq!(old_pixel, new_pixel);
+        for channels in [3, 4] {
+            let width = 10;
+            let height = 10;
+            let image = generate_image(width, height, channels, 8).unwrap();
+
+            let crop_x = 2;
+            let crop_y = 2;
+            let crop_width = 5;
+            let crop_height = 5;
+            let cropped_image = crop_image(&image, width, channels, crop_x, crop_y, crop_width, crop_height).unwrap();
+
+            // クロップされた画像のサイズが正しいか確認（crop_width * crop_height * channels）
+            assert_eq!(cropped_image.len(), crop_width * crop_height * channels);
+
+            // クロップされた画像が元の画像と一致しているか確認
+            for y in 0..crop_height {
+                for x in 0..crop_width {
+                    for c in 0..channels {
+                        let old_x = crop_x + x;
+                        let old_y = crop_y + y;
+
+                        let old_index = (old_y * width + old_x) * channels;
+                        let new_index = (y * crop_width + x) * channels;
+
+                        let old_pixel = &image[old_index + c];
+                        let new_pixel = &cropped_image[new_index + c];
+
+                        assert_eq!(old_pixel, new_pixel);
+                    }
                 }
             }
         }
     }
+
+    #[test]
+    fn test_flatten_alpha() {
+        // 不透明・透明・半透明のピクセル
+        let image = vec![
+            10, 20, 30, 255,
+            10, 20, 30, 0,
+            200, 100, 0, 128,
+        ];
+        let flattened = flatten_alpha(&image, 4, 8, &[255, 255, 255]).unwrap();
+
+        assert_eq!(flattened.len(), 3 * 3);
+        assert_eq!(flattened[0..3], [10, 20, 30]);
+        assert_eq!(flattened[3..6], [255, 255, 255]);
+        assert_eq!(flattened[6..9], [227, 177, 127]);
+    }
+
+    #[test]
+    fn test_flatten_alpha_16bit() {
+        let image = vec![
+            1000, 2000, 3000, 65535,
+            1000, 2000, 3000, 0,
+        ];
+        let flattened = flatten_alpha(&image, 4, 16, &[0, 65535, 30000]).unwrap();
+        assert_eq!(flattened, vec![1000, 2000, 3000, 0, 65535, 30000]);
+    }
+
+    #[test]
+    fn test_convert_depth() {
+        let image = vec![0, 1, 128, 257, 32767, 32768, 65535];
+        assert_eq!(convert_depth(&image, 16, 8).unwrap(), vec![0, 0, 0, 1, 127, 128, 255]);
+        // 8bit -> 16bit -> 8bit は元に戻る
+        let image = generate_image(8, 8, 3, 8).unwrap();
+        assert_eq!(convert_depth(&convert_depth(&image, 8, 16).unwrap(), 16, 8).unwrap(), image);
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let image = generate_image(4, 4, 3, 8).unwrap();
+        // はみ出すクロップ
+        assert!(matches!(crop_image(&image, 4, 3, 2, 2, 3, 1), Err(Error::InvalidDimensions(_))));
+        // サイズが合わない画像
+        assert!(matches!(commit_image::<Fr>(&image, 4, 3, 3, 8), Err(Error::InvalidDimensions(_))));
+        // 範囲外のピクセル値
+        let mut bad = image.clone();
+        bad[5] = 256;
+        assert!(matches!(
+            commit_image::<Fr>(&bad, 4, 4, 3, 8),
+            Err(Error::PixelOutOfRange { index: 5, value: 256, bit_depth: 8 })
+        ));
+        assert!(matches!(convert_depth(&image, 8, 17), Err(Error::UnsupportedBitDepth(17))));
+
+        let mut input = random_schnorr_signature_input();
+        verify_schnorr_signature(&input).unwrap();
+        input.msg_hash += Fq::one();
+        assert!(matches!(verify_schnorr_signature(&input), Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_tiles() {
+        let (width, height, channels, bit_depth) = (12, 8, 3, 8);
+        let image = generate_image(width, height, channels, bit_depth).unwrap();
+        let tiles = split_tiles(&image, width, height, channels, 4, 4).unwrap();
+        assert_eq!(tiles.len(), 6);
+        // 2 段目の最初のタイルは (0, 4) から
+        assert_eq!(tiles[3], crop_image(&image, width, channels, 0, 4, 4, 4).unwrap());
+
+        let leaves: Vec<Fr> = tiles.iter().map(|tile| commit_image(tile, 4, 4, channels, bit_depth).unwrap()).collect();
+        // 6 枚の葉は 8 枚まで 0 で埋める
+        let pair = |l: Fr, r: Fr| poseidon_hash(&[l, r]);
+        let root = pair(
+            pair(pair(leaves[0], leaves[1]), pair(leaves[2], leaves[3])),
+            pair(pair(leaves[4], leaves[5]), pair(Fr::zero(), Fr::zero())),
+        );
+        assert_eq!(merkle_root(&leaves), root);
+        assert_eq!(
+            commit_tiled_image::<Fr>(&image, width, height, channels, bit_depth, 4, 4).unwrap(),
+            tiled_commitment(width, height, channels, bit_depth, 4, 4, &leaves)
+        );
+        // タイルの形が違えば別のコミットメント
+        assert_ne!(
+            commit_tiled_image::<Fr>(&image, width, height, channels, bit_depth, 4, 4).unwrap(),
+            commit_tiled_image::<Fr>(&image, width, height, channels, bit_depth, 6, 4).unwrap()
+        );
+        assert!(matches!(split_tiles(&image, width, height, channels, 5, 4), Err(Error::InvalidDimensions(_))));
+
+        // どの葉も経路から同じ根に戻る
+        let tree = merkle_tree(&leaves);
+        assert_eq!(tree.len(), 4);
+        for (index, &leaf) in leaves.iter().enumerate() {
+            let path = merkle_path(&tree, index);
+            assert_eq!(path.len(), 3);
+            assert_eq!(merkle_root_from_path(leaf, index, &path), root);
+            assert_ne!(merkle_root_from_path(leaf, index ^ 1, &path), root);
+        }
+    }
+
+    #[test]
+    fn test_pack_image() {
+        for (channels, bit_depth) in [(3, 8), (4, 16)] {
+            let width = 20;
+            let height = 3;
+            let image = generate_image(width, height, channels, bit_depth).unwrap();
+            let packed = pack_image::<Fr>(&image, width, channels, bit_depth);
+            assert_eq!(packed.len(), height * packed_row_len(width, channels, bit_depth));
+
+            // 詰めた値を元に戻すと元の行と一致する
+            let capacity = pack_capacity(bit_depth);
+            let row_len = packed_row_len(width, channels, bit_depth);
+            for (y, row) in image.chunks(width * channels).enumerate() {
+                let unpacked: Vec<u64> = packed[y * row_len..(y + 1) * row_len]
+                    .iter()
+                    .zip(row.chunks(capacity))
+                    .flat_map(|(word, chunk)| word.to_u64_limbs(chunk.len(), bit_depth))
+                    .collect();
+                assert_eq!(unpacked, row);
+            }
+        }
+    }
 }
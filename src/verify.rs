@@ -0,0 +1,488 @@
+//! Proof verification. Nothing here depends on the prover or the circuit
+//! gadgets, so light clients only need this module, the bundle format and the
+//! verifying key.
+
+use std::fmt;
+
+use halo2_base::halo2_proofs::{
+    arithmetic::CurveAffine,
+    halo2curves::bn256::{Bn256, Fq, Fr, G1Affine},
+    plonk::{verify_proof, Error, VerifyingKey},
+    poly::{
+        commitment::Params,
+        kzg::{
+            commitment::{KZGCommitmentScheme, ParamsKZG},
+            multiopen::VerifierSHPLONK,
+            strategy::SingleStrategy,
+        },
+    },
+    transcript::{Blake2bRead, Challenge255, EncodedChallenge, TranscriptReadBuffer},
+};
+use snark_verifier::{
+    loader::native::NativeLoader,
+    pcs::{
+        kzg::{Bdfg21, KzgAccumulator, KzgAs, KzgDecidingKey},
+        AccumulationDecider,
+    },
+    system::halo2::{compile, Config},
+    util::arithmetic::{fe_from_limbs, fe_to_fe},
+    verifier::plonk::PlonkProtocol,
+};
+#[cfg(feature = "evm")]
+use snark_verifier::system::halo2::transcript::evm::EvmTranscript;
+#[cfg(feature = "prover")]
+use snark_verifier::system::halo2::transcript::halo2::{self, ChallengeScalar};
+
+use crate::{
+    bundle::{
+        AggregateBundle, HistoryBundle, ImageCommitment, ProofBundle, Statement, Transformation, Transcript,
+        BUNDLE_VERSION,
+    },
+    config::{DEFAULT_LIMB_BITS, DEFAULT_NUM_LIMBS},
+    keys::CircuitSpec,
+    util::{self, POSEIDON_RATE, POSEIDON_R_F, POSEIDON_R_P, POSEIDON_T},
+};
+#[cfg(feature = "prover")]
+use crate::{bundle::TiledBundle, tile::TiledSpec};
+
+/// The Poseidon transcript, with the parameters of the image commitment hash.
+/// It comes with snark-verifier's halo2 loader, so only `prover` builds have it.
+#[cfg(feature = "prover")]
+pub type PoseidonTranscript<L, S> =
+    halo2::PoseidonTranscript<G1Affine, L, S, POSEIDON_T, POSEIDON_RATE, POSEIDON_R_F, POSEIDON_R_P>;
+
+/// The KZG accumulator of an aggregation circuit is exposed as the four base
+/// field coordinates of its two points, each split into limbs.
+pub const ACCUMULATOR_LIMBS: usize = DEFAULT_NUM_LIMBS;
+pub const ACCUMULATOR_LIMB_BITS: usize = DEFAULT_LIMB_BITS;
+pub const ACCUMULATOR_INSTANCES: usize = 4 * ACCUMULATOR_LIMBS;
+
+/// Public instances of the history circuit: its accumulator, then the digest
+/// of its own verifying key, the original and latest image commitments and the
+/// number of edits.
+pub const HISTORY_DIGEST_ROW: usize = ACCUMULATOR_INSTANCES;
+pub const HISTORY_ORIGINAL_ROW: usize = ACCUMULATOR_INSTANCES + 1;
+pub const HISTORY_LATEST_ROW: usize = ACCUMULATOR_INSTANCES + 2;
+pub const HISTORY_EDITS_ROW: usize = ACCUMULATOR_INSTANCES + 3;
+pub const HISTORY_INSTANCES: usize = ACCUMULATOR_INSTANCES + 4;
+
+/// What a valid proof bundle establishes.
+pub type Verified = Statement;
+
+#[derive(Debug)]
+pub enum VerifyError {
+    UnsupportedVersion(u32),
+    /// The bundle was made for a different verifying key.
+    VkMismatch,
+    /// The bundle's circuit, edit or image shapes are not the ones the keys
+    /// prove. None of them are public instances, so the proof cannot vouch
+    /// for them.
+    StatementMismatch,
+    /// The public instances do not match the commitments and edit in the bundle.
+    InstanceMismatch { expected: Vec<Fr>, found: Vec<Fr> },
+    InvalidProof(Error),
+    /// Keccak proofs without the `evm` feature, or Poseidon proofs without
+    /// `prover`.
+    UnsupportedTranscript(Transcript),
+    /// The accumulator of an aggregated proof does not decode to curve points
+    /// or fails the pairing check, so some aggregated proof is invalid.
+    InvalidAccumulator,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bundle version {version}, expected {BUNDLE_VERSION}")
+            }
+            VerifyError::VkMismatch => write!(f, "proof bundle is for a different verifying key"),
+            VerifyError::StatementMismatch => {
+                write!(f, "proof bundle claims a circuit, edit or image shape its keys do not prove")
+            }
+            VerifyError::InstanceMismatch { expected, found } => write!(
+                f,
+                "public instances do not match the bundle: expected {expected:?}, found {found:?}"
+            ),
+            VerifyError::InvalidProof(e) => write!(f, "invalid proof: {e:?}"),
+            VerifyError::UnsupportedTranscript(transcript) => {
+                write!(f, "this build cannot verify {transcript:?} transcripts")
+            }
+            VerifyError::InvalidAccumulator => write!(f, "invalid accumulator in aggregated proof"),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// The public instances a circuit exposes for `statement`: the original and
+/// result commitments, followed by the crop offset when it is revealed.
+pub fn statement_instances(statement: &Statement) -> Vec<Fr> {
+    let mut instances = vec![statement.original.commitment, statement.result.commitment];
+    if let Transformation::Crop { x: Some(x), y: Some(y), .. } = statement.transformation {
+        instances.extend([Fr::from(x as u64), Fr::from(y as u64)]);
+    }
+    instances
+}
+
+/// [`statement_instances`] for the statement in `bundle`.
+pub fn expected_instances(bundle: &ProofBundle) -> Vec<Fr> {
+    statement_instances(&bundle.statement())
+}
+
+/// Checks a raw proof against `instances` of a single instance column.
+pub fn verify_proof_bytes(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instances: &[Fr],
+    proof: &[u8],
+) -> Result<(), Error> {
+    check::<Challenge255<_>, Blake2bRead<_, G1Affine, Challenge255<_>>>(params, vk, instances, proof)
+}
+
+/// [`verify_proof_bytes`] for a proof made with `transcript`.
+pub fn verify_proof_bytes_with(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instances: &[Fr],
+    proof: &[u8],
+    transcript: Transcript,
+) -> Result<(), VerifyError> {
+    match transcript {
+        Transcript::Blake2b => verify_proof_bytes(params, vk, instances, proof),
+        #[cfg(feature = "evm")]
+        Transcript::Keccak => {
+            check::<Challenge255<_>, EvmTranscript<G1Affine, NativeLoader, _, _>>(params, vk, instances, proof)
+        }
+        #[cfg(feature = "prover")]
+        Transcript::Poseidon => {
+            check::<ChallengeScalar<_>, PoseidonTranscript<NativeLoader, _>>(params, vk, instances, proof)
+        }
+        #[allow(unreachable_patterns)]
+        transcript => return Err(VerifyError::UnsupportedTranscript(transcript)),
+    }
+    .map_err(VerifyError::InvalidProof)
+}
+
+fn check<'a, E, T>(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    instances: &[Fr],
+    proof: &'a [u8],
+) -> Result<(), Error>
+where
+    E: EncodedChallenge<G1Affine>,
+    T: TranscriptReadBuffer<&'a [u8], G1Affine, E>,
+{
+    let mut transcript = T::init(proof);
+    let strategy = SingleStrategy::new(params);
+    verify_proof::<KZGCommitmentScheme<Bn256>, VerifierSHPLONK<'_, Bn256>, E, T, SingleStrategy<'_, Bn256>>(
+        params,
+        vk,
+        strategy,
+        &[&[instances]],
+        &mut transcript,
+    )
+}
+
+/// Verifies a proof bundle: the format version, that it was made for `vk`,
+/// that `spec`, the spec of the keys, proves its statement, that its instances
+/// are the ones implied by its commitments and edit, and finally the proof
+/// itself with the transcript the bundle names.
+pub fn verify(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    spec: &CircuitSpec,
+    bundle: &ProofBundle,
+) -> Result<Verified, VerifyError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(VerifyError::UnsupportedVersion(bundle.version));
+    }
+    if bundle.vk_hash != vk.transcript_repr() {
+        return Err(VerifyError::VkMismatch);
+    }
+    if !spec.proves(&bundle.statement()) {
+        return Err(VerifyError::StatementMismatch);
+    }
+    let expected = expected_instances(bundle);
+    if bundle.instances != expected {
+        return Err(VerifyError::InstanceMismatch { expected, found: bundle.instances.clone() });
+    }
+    verify_proof_bytes_with(params, vk, &bundle.instances, &bundle.proof, bundle.transcript)?;
+
+    Ok(bundle.statement())
+}
+
+/// Verifies an aggregated bundle the way [`verify`] does a single one, with
+/// `inner` the spec of the aggregated proofs' keys, then decides the
+/// accumulator in its first [`ACCUMULATOR_INSTANCES`] instances. The
+/// aggregation circuit only checks the folded proofs up to that final pairing,
+/// so a valid outer proof alone proves nothing about them.
+pub fn verify_aggregate(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    inner: &CircuitSpec,
+    bundle: &AggregateBundle,
+) -> Result<Vec<Verified>, VerifyError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(VerifyError::UnsupportedVersion(bundle.version));
+    }
+    if bundle.vk_hash != vk.transcript_repr() {
+        return Err(VerifyError::VkMismatch);
+    }
+    if !bundle.statements.iter().all(|statement| inner.proves(statement)) {
+        return Err(VerifyError::StatementMismatch);
+    }
+    let accumulator = bundle.instances.get(..ACCUMULATOR_INSTANCES).ok_or(VerifyError::InvalidAccumulator)?;
+    let mut expected = accumulator.to_vec();
+    expected.extend(bundle.statements.iter().flat_map(statement_instances));
+    if bundle.instances != expected {
+        return Err(VerifyError::InstanceMismatch { expected, found: bundle.instances.clone() });
+    }
+    verify_proof_bytes_with(params, vk, &bundle.instances, &bundle.proof, bundle.transcript)?;
+    decide(params, accumulator)?;
+
+    Ok(bundle.statements.clone())
+}
+
+/// Verifies a tiled bundle like [`verify_aggregate`], with `spec` the spec of
+/// the tiled keys. The instances after the accumulator are the tiled
+/// commitments of the original and the result; the edits and tile size are
+/// not, so only `spec` vouches for them. [`TiledSpec`] comes with the tiled
+/// circuit, so only `prover` builds have this.
+#[cfg(feature = "prover")]
+pub fn verify_tiled(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    spec: &TiledSpec,
+    bundle: &TiledBundle,
+) -> Result<(), VerifyError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(VerifyError::UnsupportedVersion(bundle.version));
+    }
+    if bundle.vk_hash != vk.transcript_repr() {
+        return Err(VerifyError::VkMismatch);
+    }
+    if !spec.proves(bundle) {
+        return Err(VerifyError::StatementMismatch);
+    }
+    let accumulator = bundle.instances.get(..ACCUMULATOR_INSTANCES).ok_or(VerifyError::InvalidAccumulator)?;
+    let mut expected = accumulator.to_vec();
+    expected.extend([bundle.original.commitment, bundle.result.commitment]);
+    if bundle.instances != expected {
+        return Err(VerifyError::InstanceMismatch { expected, found: bundle.instances.clone() });
+    }
+    verify_proof_bytes_with(params, vk, &bundle.instances, &bundle.proof, bundle.transcript)?;
+    decide(params, accumulator)
+}
+
+/// snark-verifier's view of the history circuit with key `vk`, whose first
+/// instances are an accumulator that the next edit's circuit folds in.
+pub fn history_protocol(params: &ParamsKZG<Bn256>, vk: &VerifyingKey<G1Affine>) -> PlonkProtocol<G1Affine> {
+    let accumulator = (0..ACCUMULATOR_INSTANCES).map(|i| (0, i)).collect();
+    compile(
+        params,
+        vk,
+        Config::kzg().with_num_instance(vec![HISTORY_INSTANCES]).with_accumulator_indices(Some(vec![accumulator])),
+    )
+}
+
+/// Poseidon hash of a key's fixed commitments, coordinates reduced into `Fr`,
+/// and its transcript initial state. The history circuit verifies the previous
+/// proof of a chain with the key as a witness and exposes this digest of it.
+pub fn preprocessed_digest(protocol: &PlonkProtocol<G1Affine>) -> Fr {
+    let inputs: Vec<Fr> = protocol
+        .preprocessed
+        .iter()
+        .flat_map(|point| [point.x, point.y])
+        .map(fe_to_fe)
+        .chain(protocol.transcript_initial_state)
+        .collect();
+    util::poseidon_hash(&inputs)
+}
+
+/// Verifies the latest proof of an edit chain. On top of the checks of
+/// [`verify`] and the accumulator decision of [`verify_aggregate`], the key
+/// digest in the instances must be that of `vk`: every earlier proof in the
+/// chain was checked against that digest, and only this check ties it to a
+/// trusted key. The bundle's `original`, `latest` and `edits` then hold.
+/// `inner` is the spec of the edit keys, which gives the images' shapes; only
+/// their commitments are instances.
+pub fn verify_history(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    inner: &CircuitSpec,
+    bundle: &HistoryBundle,
+) -> Result<(), VerifyError> {
+    if bundle.version != BUNDLE_VERSION {
+        return Err(VerifyError::UnsupportedVersion(bundle.version));
+    }
+    if bundle.vk_hash != vk.transcript_repr() {
+        return Err(VerifyError::VkMismatch);
+    }
+    let shape = |image: &ImageCommitment| (image.width, image.height, image.channels, image.bit_depth);
+    if shape(&bundle.original) != (inner.width, inner.height, inner.channels, inner.bit_depth)
+        || shape(&bundle.latest) != (inner.crop_width, inner.crop_height, inner.channels, inner.bit_depth)
+    {
+        return Err(VerifyError::StatementMismatch);
+    }
+    let accumulator = bundle.instances.get(..ACCUMULATOR_INSTANCES).ok_or(VerifyError::InvalidAccumulator)?;
+    let mut expected = accumulator.to_vec();
+    expected.extend([
+        preprocessed_digest(&history_protocol(params, vk)),
+        bundle.original.commitment,
+        bundle.latest.commitment,
+        Fr::from(bundle.edits as u64),
+    ]);
+    if bundle.instances != expected {
+        return Err(VerifyError::InstanceMismatch { expected, found: bundle.instances.clone() });
+    }
+    verify_proof_bytes_with(params, vk, &bundle.instances, &bundle.proof, Transcript::Poseidon)?;
+    decide(params, accumulator)
+}
+
+/// The pairing check `e(lhs, g2) = e(rhs, s_g2)` on an accumulator given as
+/// limbs.
+fn decide(params: &ParamsKZG<Bn256>, limbs: &[Fr]) -> Result<(), VerifyError> {
+    let coordinates: Vec<Fq> = limbs
+        .chunks(ACCUMULATOR_LIMBS)
+        .map(|limbs| fe_from_limbs::<_, _, ACCUMULATOR_LIMBS, ACCUMULATOR_LIMB_BITS>(limbs.try_into().unwrap()))
+        .collect();
+    let point = |x: Fq, y: Fq| Option::from(G1Affine::from_xy(x, y)).ok_or(VerifyError::InvalidAccumulator);
+    let accumulator =
+        KzgAccumulator::new(point(coordinates[0], coordinates[1])?, point(coordinates[2], coordinates[3])?);
+    let dk: KzgDecidingKey<Bn256> = (params.get_g()[0], params.g2(), params.s_g2()).into();
+    KzgAs::<Bn256, Bdfg21>::decide(&dk, accumulator).map_err(|_| VerifyError::InvalidAccumulator)
+}
+
+#[cfg(all(test, feature = "prover"))]
+mod tests {
+    use super::*;
+    use crate::{
+        bundle::{CircuitId, ImageCommitment},
+        circuit::{build_circuit, crop_and_commit},
+        config::{CircuitConfig, CircuitParams},
+        prover::{gen_proof, gen_proof_with, keygen},
+        srs::SrsManager,
+        util,
+    };
+    use halo2_base::gates::{
+        builder::{CircuitBuilderStage, GateThreadBuilder},
+        RangeChip,
+    };
+
+    #[test]
+    fn test_verify_bundle() {
+        let k = 13;
+        let (width, height, channels, bit_depth) = (16, 8, 3, 8);
+        let (x, y, w, h) = (3, 2, 9, 5);
+        let image = util::generate_image(width, height, channels, bit_depth).unwrap();
+        let mut config = CircuitConfig::new(CircuitParams::new(k, 12, Some(9)));
+
+        let circuit = |stage, image: &util::Image, config: &mut CircuitConfig| {
+            let mut builder = GateThreadBuilder::from_stage(stage);
+            let range = RangeChip::default(config.params.lookup_bits);
+            let mut assigned_instances = vec![];
+            crop_and_commit(
+                &mut builder,
+                &range,
+                image,
+                width,
+                height,
+                channels,
+                bit_depth,
+                x,
+                y,
+                w,
+                h,
+                &mut assigned_instances,
+            )
+            .unwrap();
+            build_circuit(stage, builder, assigned_instances, config).unwrap()
+        };
+
+        let params = SrsManager::new("params").dev_mode(true).load(k as u32).unwrap();
+        let keygen_circuit = circuit(CircuitBuilderStage::Keygen, &vec![0; image.len()], &mut config);
+        let pk = keygen(&params, &keygen_circuit).unwrap();
+        config.break_points = keygen_circuit.break_points();
+
+        let prover_circuit = circuit(CircuitBuilderStage::Prover, &image, &mut config);
+        let instances = prover_circuit.instance();
+        let proof = gen_proof(&params, &pk, prover_circuit, &instances).unwrap();
+
+        let cropped = util::crop_image(&image, width, channels, x, y, w, h).unwrap();
+        let commitment = |image: &util::Image, width, height| ImageCommitment {
+            width,
+            height,
+            channels,
+            bit_depth,
+            commitment: util::commit_image(image, width, height, channels, bit_depth).unwrap(),
+        };
+        let bundle = ProofBundle::new(
+            CircuitId::new("crop"),
+            pk.get_vk(),
+            Transcript::Blake2b,
+            Transformation::Crop { x: Some(x), y: Some(y), width: w, height: h },
+            commitment(&image, width, height),
+            commitment(&cropped, w, h),
+            instances,
+            proof,
+        );
+
+        // the keys of a crop_and_commit circuit, whose offset is not revealed
+        let spec = CircuitSpec {
+            circuit: CircuitId::new("crop"),
+            width,
+            height,
+            channels,
+            bit_depth,
+            crop_width: w,
+            crop_height: h,
+            reveal_offset: false,
+            rows_per_leaf: None,
+            hiding: false,
+        };
+
+        // the offset is part of the circuit here, not an instance
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &bundle), Err(VerifyError::StatementMismatch)));
+        let bundle = ProofBundle {
+            transformation: Transformation::Crop { x: None, y: None, width: w, height: h },
+            ..bundle
+        };
+        let verified = verify(&params, pk.get_vk(), &spec, &bundle).unwrap();
+        assert_eq!(verified.result, bundle.result);
+
+        // nothing but the commitments is an instance, so the rest must match the keys
+        let mut forged = bundle.clone();
+        forged.original.bit_depth = 16;
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &forged), Err(VerifyError::StatementMismatch)));
+        let mut forged = bundle.clone();
+        forged.circuit = CircuitId::new("edit");
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &forged), Err(VerifyError::StatementMismatch)));
+
+        let mut forged = bundle.clone();
+        forged.instances.swap(0, 1);
+        assert!(matches!(
+            verify(&params, pk.get_vk(), &spec, &forged),
+            Err(VerifyError::InstanceMismatch { .. })
+        ));
+
+        // claiming a different crop fails the proof, not just the instance check
+        let mut forged = bundle.clone();
+        forged.result.commitment =
+            util::commit_image(&vec![0; cropped.len()], w, h, channels, bit_depth).unwrap();
+        forged.instances = expected_instances(&forged);
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &forged), Err(VerifyError::InvalidProof(_))));
+
+        let mut forged = bundle.clone();
+        forged.vk_hash = Fr::from(1);
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &forged), Err(VerifyError::VkMismatch)));
+
+        // the bundle names the transcript its proof was made with
+        let prover_circuit = circuit(CircuitBuilderStage::Prover, &image, &mut config);
+        let proof = gen_proof_with(&params, &pk, prover_circuit, &bundle.instances, Transcript::Poseidon).unwrap();
+        let poseidon = ProofBundle { transcript: Transcript::Poseidon, proof, ..bundle };
+        verify(&params, pk.get_vk(), &spec, &poseidon).unwrap();
+        let mislabeled = ProofBundle { transcript: Transcript::Blake2b, ..poseidon };
+        assert!(matches!(verify(&params, pk.get_vk(), &spec, &mislabeled), Err(VerifyError::InvalidProof(_))));
+    }
+}
@@ -1,90 +1,267 @@
-// use crate::halo2_proofs::{
-//     poly::kzg::{
-//         commitment::{KZGCommitmentScheme, ParamsKZG},
-//         multiopen::ProverSHPLONK,
-//     },
-//     transcript::TranscriptWriterBuffer,
-// };
-// use crate::secp256k1::ecdsa::ECDSACircuit;
-// use crate::{
-//     halo2_proofs::{
-//         halo2curves::bn256::{Bn256, Fr, G1Affine},
-//         plonk::*,
-//         transcript::{Blake2bWrite, Challenge255},
-//         SerdeFormat,
-//     },
-// };
-// use halo2_base::{halo2_proofs::poly::commitment::Params, utils::PrimeField};
-
-// use js_sys::Uint8Array;
-// use std::io::BufReader;
-// use std::marker::PhantomData;
-// use wasm_bindgen::prelude::*;
-// use web_sys;
-
-// // wasm_bindgen_rayon requires the rustflags defined in .cargo/config
-// // to be set in order to compile. When we enable rustflags,
-// // rust-analyzer (the vscode extension) stops working, so by default,
-// // we don't compile wasm_bindgen_rayon which requires rustflags,
-// #[cfg(target_family = "wasm")]
-// pub use wasm_bindgen_rayon::init_thread_pool;
-
-// macro_rules! log {
-//     ( $( $t:tt )* ) => {
-//         web_sys::console::log_1(&format!( $( $t )* ).into());
-//     }
-// }
-
-// #[wasm_bindgen]
-// pub fn init_panic_hook() {
-//     console_error_panic_hook::set_once();
-// }
-
-// #[wasm_bindgen]
-// pub fn prove(params_ser: JsValue) -> JsValue {
-//     // parse params
-//     web_sys::console::time_with_label("Loading params");
-//     let params_vec = Uint8Array::new(&params_ser).to_vec();
-//     let params = ParamsKZG::<Bn256>::read(&mut BufReader::new(&params_vec[..])).unwrap();
-//     web_sys::console::time_end_with_label("Loading params");
-
-//     // generate proving key and verification key
-//     let circuit = ECDSACircuit::<Fr>::default();
-
-//     web_sys::console::time_with_label("Generating verifying key");
-//     let vk = keygen_vk(&params, &circuit).expect("keygen_vk should not fail");
-//     web_sys::console::time_end_with_label("Generating verifying key");
-
-//     web_sys::console::time_with_label("Generating proving key");
-//     let pk = keygen_pk(&params, vk, &circuit).expect("keygen_pk should not fail");
-//     web_sys::console::time_end_with_label("Generating proving key");
-
-//     // inputs
-//     let (r, s, msg_hash, pubkey, G) = generate_ecdsa_input();
-//     let circuit = ECDSACircuit::<Fr> {
-//         r: Some(r),
-//         s: Some(s),
-//         msghash: Some(msg_hash),
-//         pk: Some(pubkey),
-//         G,
-//         _marker: PhantomData,
-//     };
-
-//     // generating a proof
-//     web_sys::console::time_with_label("Generating proof");
-//     let rng = rand::thread_rng();
-//     let mut transcript = Blake2bWrite::<_, _, Challenge255<_>>::init(vec![]);
-//     create_proof::<
-//         KZGCommitmentScheme<Bn256>,
-//         ProverSHPLONK<'_, Bn256>,
-//         Challenge255<G1Affine>,
-//         _,
-//         Blake2bWrite<Vec<u8>, G1Affine, Challenge255<G1Affine>>,
-//         ECDSACircuit<Fr>,
-//     >(&params, &pk, &[circuit], &[&[]], rng, &mut transcript)
-//     .unwrap();
-//     let proof = transcript.finalize();
-//     web_sys::console::time_end_with_label("Generating proof");
-
-//     serde_wasm_bindgen::to_value(&proof).unwrap()
-// }
\ No newline at end of file
+//! WebAssembly bindings, built with the `wasm` feature. Keys come from
+//! `zkimg keygen`: JavaScript passes the SRS and key files as `Uint8Array`s and
+//! the key directory's `config.json` and `circuit.json` as strings. Proof
+//! bundles are returned in the binary encoding; [`verify`] also accepts JSON.
+//!
+//! Without the `prover` feature only [`verify`] is built, which keeps the
+//! circuit gadgets, keygen and the prover out of the module.
+
+use halo2_base::halo2_proofs::{
+    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    plonk::VerifyingKey,
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+    SerdeFormat,
+};
+#[cfg(feature = "prover")]
+use halo2_base::halo2_proofs::plonk::ProvingKey;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    bundle::{CircuitId, ImageCommitment, ProofBundle, Transformation},
+    config::{CircuitConfig, ConfiguredCircuit},
+    error::{Error, Result},
+    keys::CircuitSpec,
+    srs::validate_srs,
+    util::Signature,
+};
+#[cfg(feature = "prover")]
+use crate::{
+    bundle::Transcript,
+    keys::Witness,
+    util::Image,
+};
+
+/// The format `zkimg keygen` writes keys in.
+const KEY_FORMAT: SerdeFormat = SerdeFormat::RawBytes;
+
+#[wasm_bindgen]
+pub fn init_panic_hook() {
+    console_error_panic_hook::set_once();
+}
+
+/// Reads a `ParamsKZG` file, downsized to `k` if it is larger.
+fn read_params(bytes: &[u8], k: usize) -> Result<ParamsKZG<Bn256>> {
+    let mut params = ParamsKZG::<Bn256>::read(&mut &bytes[..])?;
+    validate_srs(&params)?;
+    if (params.k() as usize) < k {
+        return Err(Error::InvalidParams(format!("params are for k = {}, the keys need k = {k}", params.k())));
+    }
+    if params.k() as usize > k {
+        params.downsize(k as u32);
+    }
+    Ok(params)
+}
+
+#[cfg(feature = "prover")]
+fn read_pk(bytes: &[u8], config: &str) -> Result<(ProvingKey<G1Affine>, CircuitConfig)> {
+    let config: CircuitConfig = serde_json::from_str(config)?;
+    config.apply();
+    let pk = ProvingKey::read::<_, ConfiguredCircuit<Fr>>(&mut &bytes[..], KEY_FORMAT)?;
+    config.check_k(pk.get_vk().get_domain().k())?;
+    Ok((pk, config))
+}
+
+fn read_vk(bytes: &[u8], config: &str) -> Result<(VerifyingKey<G1Affine>, CircuitConfig)> {
+    let config: CircuitConfig = serde_json::from_str(config)?;
+    config.apply();
+    let vk = VerifyingKey::read::<_, ConfiguredCircuit<Fr>>(&mut &bytes[..], KEY_FORMAT)?;
+    config.check_k(vk.get_domain().k())?;
+    Ok((vk, config))
+}
+
+#[cfg(feature = "prover")]
+#[allow(clippy::too_many_arguments)]
+fn prove(
+    params: &[u8],
+    pk: &[u8],
+    config: &str,
+    spec: &str,
+    pixels: &[u16],
+    x: usize,
+    y: usize,
+    signature: Option<&str>,
+    witness: Option<&str>,
+) -> Result<Vec<u8>> {
+    let spec = CircuitSpec::from_json(spec)?;
+    let image: Image = pixels.iter().map(|&v| u64::from(v)).collect();
+    let salt = witness.map(Witness::from_json).transpose()?.map(|witness| witness.salt);
+    if let Some(signature) = signature {
+        Signature::from_json(signature)?.verify(spec.original_commitment(&image, salt)?.commitment)?;
+    }
+    let (pk, mut config) = read_pk(pk, config)?;
+    let params = read_params(params, config.params.k)?;
+    let (bundle, _) =
+        spec.prove::<ConfiguredCircuit<Fr>>(&params, &pk, &mut config, &image, (x, y), salt, Transcript::Blake2b)?;
+    bundle.to_bytes()
+}
+
+/// Proves the crop at (`x`, `y`) of an image given as interleaved channel
+/// values, with the keys and `circuit.json` of a `zkimg keygen` directory.
+/// Hiding keys also need `witness`, the salt file `zkimg commit --salt` wrote.
+#[cfg(feature = "prover")]
+#[wasm_bindgen]
+pub fn prove_crop(
+    params: &[u8],
+    pk: &[u8],
+    config: &str,
+    spec: &str,
+    pixels: &[u16],
+    x: usize,
+    y: usize,
+    witness: Option<String>,
+) -> std::result::Result<Vec<u8>, JsError> {
+    Ok(prove(params, pk, config, spec, pixels, x, y, None, witness.as_deref())?)
+}
+
+/// Like [`prove_crop`], but first checks the JSON Schnorr signature over the
+/// original image's commitment, the same file `zkimg prove --sig` takes, so a
+/// bundle is only produced for an image its owner signed.
+#[cfg(feature = "prover")]
+#[wasm_bindgen]
+pub fn prove_provenance(
+    params: &[u8],
+    pk: &[u8],
+    config: &str,
+    spec: &str,
+    pixels: &[u16],
+    x: usize,
+    y: usize,
+    signature: &str,
+    witness: Option<String>,
+) -> std::result::Result<Vec<u8>, JsError> {
+    Ok(prove(params, pk, config, spec, pixels, x, y, Some(signature), witness.as_deref())?)
+}
+
+/// The outcome of [`verify`]: what the bundle claims, and whether it holds.
+/// The claims are bound to the keys only when `valid` is true.
+#[derive(Serialize)]
+struct Verdict {
+    valid: bool,
+    /// Why the bundle was rejected.
+    error: Option<String>,
+    circuit: CircuitId,
+    transformation: Transformation,
+    original: ImageCommitment,
+    result: ImageCommitment,
+    /// The key that signed the original image, if a signature was given.
+    signer: Option<Signer>,
+}
+
+#[derive(Serialize)]
+struct Signer {
+    pk_x: String,
+    pk_y: String,
+}
+
+fn read_bundle(bytes: &[u8]) -> Result<ProofBundle> {
+    match std::str::from_utf8(bytes) {
+        Ok(json) if json.trim_start().starts_with('{') => ProofBundle::from_json(json),
+        _ => ProofBundle::from_bytes(bytes),
+    }
+}
+
+/// Verifies a proof bundle in either encoding against the keys and their
+/// `spec`, and with a `signature` also that it signs the original image.
+/// Returns a verdict object with the bundle's image commitments and edit,
+/// which only hold with `valid: true`; a proof or signature that does not
+/// check out gives `valid: false`, while malformed keys or bundles throw.
+#[wasm_bindgen]
+pub fn verify(
+    params: &[u8],
+    vk: &[u8],
+    config: &str,
+    spec: &str,
+    bundle: &[u8],
+    signature: Option<String>,
+) -> std::result::Result<JsValue, JsError> {
+    let bundle = read_bundle(bundle)?;
+    let spec = CircuitSpec::from_json(spec)?;
+    let (vk, config) = read_vk(vk, config)?;
+    let params = read_params(params, config.params.k)?;
+
+    let checked = crate::verify::verify(&params, &vk, &spec, &bundle).map_err(Error::from).and_then(|verified| {
+        let Some(signature) = &signature else { return Ok(None) };
+        let signature = Signature::from_json(signature)?;
+        signature.verify(verified.original.commitment)?;
+        Ok(Some(Signer { pk_x: signature.pk_x, pk_y: signature.pk_y }))
+    });
+    let (valid, error, signer) = match checked {
+        Ok(signer) => (true, None, signer),
+        Err(e) => (false, Some(e.to_string()), None),
+    };
+    let verdict = Verdict {
+        valid,
+        error,
+        circuit: bundle.circuit,
+        transformation: bundle.transformation,
+        original: bundle.original,
+        result: bundle.result,
+        signer,
+    };
+    serde_wasm_bindgen::to_value(&verdict).map_err(|e| JsError::new(&e.to_string()))
+}
+
+#[cfg(all(test, feature = "prover", target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use crate::{config::CircuitParams, keys::CROP_CIRCUIT, prover::keygen, util};
+    use halo2_base::gates::builder::CircuitBuilderStage;
+    use rand::rngs::OsRng;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn test_prove_and_verify() {
+        let k = 12;
+        let spec = CircuitSpec {
+            circuit: CircuitId::new(CROP_CIRCUIT),
+            width: 8,
+            height: 4,
+            channels: 3,
+            bit_depth: 8,
+            crop_width: 4,
+            crop_height: 2,
+            reveal_offset: true,
+            rows_per_leaf: None,
+            hiding: false,
+        };
+        let mut config = CircuitConfig::new(CircuitParams::new(k, k - 1, Some(9)));
+        let circuit =
+            spec.circuit(CircuitBuilderStage::Keygen, &spec.blank(), (0, 0), None, &mut config).unwrap();
+        let params = ParamsKZG::<Bn256>::setup(k as u32, OsRng);
+        let pk = keygen(&params, &circuit).unwrap();
+        config.break_points = circuit.break_points();
+
+        let mut params_bytes = vec![];
+        params.write(&mut params_bytes).unwrap();
+        let pk_bytes = pk.to_bytes(KEY_FORMAT);
+        let vk_bytes = pk.get_vk().to_bytes(KEY_FORMAT);
+        let config = serde_json::to_string(&config).unwrap();
+        let spec = serde_json::to_string(&spec).unwrap();
+        let image = util::generate_image(8, 4, 3, 8).unwrap();
+        let pixels: Vec<u16> = image.iter().map(|&v| v as u16).collect();
+
+        let bundle = prove_crop(&params_bytes, &pk_bytes, &config, &spec, &pixels, 2, 1, None)
+            .unwrap_or_else(|_| panic!("prove_crop failed"));
+        let verdict = |bundle: &[u8]| -> serde_json::Value {
+            let verdict = verify(&params_bytes, &vk_bytes, &config, &spec, bundle, None)
+                .unwrap_or_else(|_| panic!("verify failed"));
+            serde_wasm_bindgen::from_value(verdict).unwrap()
+        };
+        let verified = verdict(&bundle);
+        assert_eq!(verified["valid"], true);
+        assert_eq!(verified["transformation"]["crop"]["x"], 2);
+        assert_eq!(verified["transformation"]["crop"]["y"], 1);
+
+        let mut tampered = ProofBundle::from_bytes(&bundle).unwrap();
+        tampered.transformation = Transformation::Crop { x: Some(0), y: Some(1), width: 4, height: 2 };
+        let rejected = verdict(tampered.to_json().unwrap().as_bytes());
+        assert_eq!(rejected["valid"], false);
+        assert!(rejected["error"].is_string());
+
+        // a signature that cannot be over this image
+        let zero = "00".repeat(32);
+        let signature = format!(r#"{{ "r": "{zero}", "s": "{zero}", "pk_x": "{zero}", "pk_y": "{zero}" }}"#);
+        assert!(prove_provenance(&params_bytes, &pk_bytes, &config, &spec, &pixels, 2, 1, &signature, None).is_err());
+    }
+}